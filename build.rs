@@ -1,5 +1,8 @@
 fn main() {
-    println!("cargo:rustc-link-arg-bins=-Tlinkall.x");
-    println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    // only the firmware binary needs the esp linker scripts
+    if std::env::var_os("CARGO_FEATURE_ESP32").is_some() {
+        println!("cargo:rustc-link-arg-bins=-Tlinkall.x");
+        println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    }
 
 }
@@ -0,0 +1,107 @@
+use crate::radio::WiFiRate;
+
+// Air time of DSSS/CCK frames (IEEE 802.11 clauses 15 and 16) and the Duration/ID values derived
+// from it. All times are in µs and computed in u32, callers clamp when writing a Duration field.
+
+pub const SIFS_MICROS: u32 = 10;
+// 144 bit preamble and 48 bit PLCP header, both sent at 1M
+const LONG_PLCP_MICROS: u32 = 192;
+// 72 bit preamble at 1M and 48 bit PLCP header at 2M
+const SHORT_PLCP_MICROS: u32 = 96;
+
+pub const FCS_SIZE: usize = 4;
+// frame control, duration, receiver address and FCS
+pub const ACK_FRAME_SIZE: usize = 14;
+
+// Data rate in units of 500kbps, the same unit the supported rates element uses.
+fn rate_500kbps(rate: WiFiRate) -> u32 {
+    match rate {
+        WiFiRate::PhyRate1ML => 2,
+        WiFiRate::PhyRate2ML | WiFiRate::PhyRate2MS => 4,
+        WiFiRate::PhyRate5ML | WiFiRate::PhyRate5MS => 11,
+        WiFiRate::PhyRate11ML | WiFiRate::PhyRate11MS => 22,
+    }
+}
+
+fn plcp_micros(rate: WiFiRate) -> u32 {
+    match rate {
+        WiFiRate::PhyRate1ML | WiFiRate::PhyRate2ML | WiFiRate::PhyRate5ML | WiFiRate::PhyRate11ML => LONG_PLCP_MICROS,
+        WiFiRate::PhyRate2MS | WiFiRate::PhyRate5MS | WiFiRate::PhyRate11MS => SHORT_PLCP_MICROS,
+    }
+}
+
+// Time on air of an MPDU of `frame_size` bytes, including the FCS.
+pub fn air_time_micros(rate: WiFiRate, frame_size: usize) -> u32 {
+    let bits = frame_size as u32 * 8;
+    plcp_micros(rate) + (bits * 2).div_ceil(rate_500kbps(rate))
+}
+
+// Control responses go out at the highest basic rate that isn't faster than the frame they answer.
+// We only advertise 1M and 2M as basic rates, the preamble stays the same.
+pub fn control_response_rate(rate: WiFiRate) -> WiFiRate {
+    match rate {
+        WiFiRate::PhyRate1ML => WiFiRate::PhyRate1ML,
+        WiFiRate::PhyRate2ML | WiFiRate::PhyRate5ML | WiFiRate::PhyRate11ML => WiFiRate::PhyRate2ML,
+        WiFiRate::PhyRate2MS | WiFiRate::PhyRate5MS | WiFiRate::PhyRate11MS => WiFiRate::PhyRate2MS,
+    }
+}
+
+// Duration/ID of a unicast frame sent at `rate`, which reserves the medium for the ACK.
+pub fn acked_frame_duration(rate: WiFiRate) -> u16 {
+    clamp_duration(SIFS_MICROS + air_time_micros(control_response_rate(rate), ACK_FRAME_SIZE))
+}
+
+// Duration/ID values above this would be read as an AID.
+pub const MAX_DURATION_MICROS: u32 = 0x7fff;
+
+pub fn clamp_duration(micros: u32) -> u16 {
+    micros.min(MAX_DURATION_MICROS) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn air_time_for_every_rate() {
+        // 100 bytes are 800 bits, CCK rounds up to whole microseconds
+        assert_eq!(air_time_micros(WiFiRate::PhyRate1ML, 100), 192 + 800);
+        assert_eq!(air_time_micros(WiFiRate::PhyRate2ML, 100), 192 + 400);
+        assert_eq!(air_time_micros(WiFiRate::PhyRate2MS, 100), 96 + 400);
+        assert_eq!(air_time_micros(WiFiRate::PhyRate5ML, 100), 192 + 146);
+        assert_eq!(air_time_micros(WiFiRate::PhyRate5MS, 100), 96 + 146);
+        assert_eq!(air_time_micros(WiFiRate::PhyRate11ML, 100), 192 + 73);
+        assert_eq!(air_time_micros(WiFiRate::PhyRate11MS, 100), 96 + 73);
+    }
+
+    #[test]
+    fn ack_air_time() {
+        assert_eq!(air_time_micros(WiFiRate::PhyRate2ML, ACK_FRAME_SIZE), 248);
+        assert_eq!(air_time_micros(WiFiRate::PhyRate2MS, ACK_FRAME_SIZE), 152);
+    }
+
+    #[test]
+    fn acked_frame_durations() {
+        assert_eq!(acked_frame_duration(WiFiRate::PhyRate2MS), 162);
+        assert_eq!(acked_frame_duration(WiFiRate::PhyRate2ML), 258);
+        assert_eq!(acked_frame_duration(WiFiRate::PhyRate1ML), 314);
+        // answered at the highest basic rate, 2M
+        assert_eq!(acked_frame_duration(WiFiRate::PhyRate11MS), 162);
+        assert_eq!(acked_frame_duration(WiFiRate::PhyRate5ML), 258);
+    }
+
+    #[test]
+    fn large_frames_do_not_overflow() {
+        // 10000 * 8 bits overflowed the old u16 math
+        assert_eq!(air_time_micros(WiFiRate::PhyRate1ML, 10000), 192 + 80000);
+        assert_eq!(clamp_duration(air_time_micros(WiFiRate::PhyRate1ML, 10000)), 0x7fff);
+    }
+
+    #[test]
+    fn clamp_duration_saturates() {
+        assert_eq!(clamp_duration(162), 162);
+        assert_eq!(clamp_duration(MAX_DURATION_MICROS), 0x7fff);
+        assert_eq!(clamp_duration(MAX_DURATION_MICROS + 1), 0x7fff);
+        assert_eq!(clamp_duration(u32::MAX), 0x7fff);
+    }
+}
@@ -15,6 +15,7 @@ use foa::bg_task::FoARunner;
 use foa::{FoAResources, VirtualInterface};
 use foa_dswifi::{DsWiFiInitInfo, DsWiFiInterface, DsWiFiInterfaceControlEvent, DsWiFiInterfaceControlEventResponse, DsWiFiSharedResources, DsWifiClientMaskMath};
 use foa_dswifi::pictochat_application::{PictoChatApplication, PictoChatUserManager};
+use foa_dswifi::foa_radio::FoARadio;
 use foa_dswifi::runner::DsWiFiRunner;
 
 use {esp_backtrace as _, defmt as _};
@@ -49,7 +50,7 @@ async fn foa_task(mut foa_runner: FoARunner<'static>) -> ! {
 }
 
 #[embassy_executor::task]
-async fn dswifi_task(mut sta_runner: DsWiFiRunner<'static, 'static>) -> ! {
+async fn dswifi_task(mut sta_runner: DsWiFiRunner<'static, FoARadio<'static, 'static>>) -> ! {
     sta_runner.run().await
 }
 
@@ -76,11 +77,13 @@ async fn main(spawner: Spawner) {
     );
     spawner.spawn(foa_task(foa_runner)).unwrap();
 
-    let ds_resources = mk_static!(DsWiFiSharedResources<'static>, DsWiFiSharedResources::default());
+    let ds_radio = mk_static!(FoARadio<'static, 'static>, FoARadio::new(mk_static!(VirtualInterface<'static>, ds_vif)));
+    let ds_resources = mk_static!(DsWiFiSharedResources<FoARadio<'static, 'static>>, DsWiFiSharedResources::default());
     let (ds_control,ds_runner) = foa_dswifi::new_ds_wifi_interface(
-        mk_static!(VirtualInterface<'static>, ds_vif),
-        ds_resources
-    );
+        ds_radio,
+        ds_resources,
+        DsWiFiInitInfo::default()
+    ).expect("failed to create ds wifi interface");
     spawner.spawn(dswifi_task(ds_runner)).unwrap();
 
     let mut pictochat_app = PictoChatApplication {
@@ -0,0 +1,84 @@
+use ieee80211::scroll;
+use crate::DsWiFiClientState;
+use crate::radio::DsWiFiRadioError;
+
+// Errors of the public API: why a frame couldn't be parsed, why a client state change was refused,
+// and why the runner turned down a control request or the init config.
+
+// Why a DS WiFi or PictoChat frame couldn't be parsed. Encoders still use scroll::Error, since
+// they have to plug into the ieee80211 frame writers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DsWiFiPacketError {
+    // a field needed more bytes than were left in the input
+    Truncated { needed: usize, available: usize },
+    // a field started past the end of the input
+    OutOfBounds { offset: usize },
+    // a length field contradicts the frame layout
+    BadLength { field: &'static str, value: usize },
+    UnknownType { field: &'static str, value: u16 },
+    BadMagic { field: &'static str },
+    OversizePayload { size: usize, max: usize },
+    // anything else the reader rejected
+    Malformed { reason: &'static str },
+}
+
+impl From<scroll::Error> for DsWiFiPacketError {
+    fn from(error: scroll::Error) -> Self {
+        match error {
+            scroll::Error::TooBig { size, len } => DsWiFiPacketError::Truncated { needed: size, available: len },
+            scroll::Error::BadOffset(offset) => DsWiFiPacketError::OutOfBounds { offset },
+            scroll::Error::BadInput { msg, .. } => DsWiFiPacketError::Malformed { reason: msg },
+            #[allow(unreachable_patterns)]
+            _ => DsWiFiPacketError::Malformed { reason: "unknown scroll error" },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DsWiFiClientStateError {
+    UnknownClient,
+    InvalidTransition { from: DsWiFiClientState, to: DsWiFiClientState },
+}
+
+// Why the runner refused a control request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DsWiFiControlError {
+    // hosting is paused while a scan runs
+    Scanning,
+    // a scan would leave the connected clients behind
+    ClientsConnected,
+    Radio(DsWiFiRadioError),
+    ClientState(DsWiFiClientStateError),
+    InvalidParameter(&'static str),
+    // the runner answered with a response that doesn't belong to the request
+    UnexpectedResponse,
+}
+
+impl From<DsWiFiRadioError> for DsWiFiControlError {
+    fn from(error: DsWiFiRadioError) -> Self {
+        DsWiFiControlError::Radio(error)
+    }
+}
+
+impl From<DsWiFiClientStateError> for DsWiFiControlError {
+    fn from(error: DsWiFiClientStateError) -> Self {
+        DsWiFiControlError::ClientState(error)
+    }
+}
+
+// Why new_ds_wifi_interface refused the init config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DsWiFiInitError {
+    InvalidParameter(&'static str),
+    Radio(DsWiFiRadioError),
+}
+
+impl From<DsWiFiRadioError> for DsWiFiInitError {
+    fn from(error: DsWiFiRadioError) -> Self {
+        DsWiFiInitError::Radio(error)
+    }
+}
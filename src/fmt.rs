@@ -0,0 +1,72 @@
+// Logging goes through defmt on the ESP32, on the host the macros swallow their arguments so the
+// protocol core doesn't need a defmt logger to link.
+
+#[cfg(feature = "defmt")]
+macro_rules! trace {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        ::defmt::trace!($s $(, $x)*)
+    };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! debug {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        ::defmt::debug!($s $(, $x)*)
+    };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! info {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        ::defmt::info!($s $(, $x)*)
+    };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! warn {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        ::defmt::warn!($s $(, $x)*)
+    };
+}
+
+#[cfg(feature = "defmt")]
+macro_rules! error {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        ::defmt::error!($s $(, $x)*)
+    };
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! trace {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        let _ = ($( & $x ),*);
+    }};
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! debug {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        let _ = ($( & $x ),*);
+    }};
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! info {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        let _ = ($( & $x ),*);
+    }};
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! warn {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        let _ = ($( & $x ),*);
+    }};
+}
+
+#[cfg(not(feature = "defmt"))]
+macro_rules! error {
+    ($s:literal $(, $x:expr)* $(,)?) => {{
+        let _ = ($( & $x ),*);
+    }};
+}
@@ -0,0 +1,121 @@
+use core::ffi::c_void;
+use core::ops::DerefMut;
+use foa::esp_wifi_hal::{self, BorrowedBuffer};
+use foa::lmac::LMacInterfaceControl;
+use foa::{RxQueueReceiver, VirtualInterface};
+use crate::radio::{DsWiFiRadio, DsWiFiRadioError, DsWiFiRxBuffer, RxFilterBank, TxErrorBehaviour, TxParameters, WiFiRate};
+
+extern "C" {
+    pub fn chip_v7_set_chan_nomac(channel: u8, idk: u8);
+    fn phy_set_most_tpw(max_txpwr: i8) -> c_void;
+}
+
+pub struct FoARadio<'vif, 'foa> {
+    interface_control: &'vif LMacInterfaceControl<'foa>,
+    interface_rx_queue: &'vif mut RxQueueReceiver<'foa>,
+}
+
+impl<'vif, 'foa> FoARadio<'vif, 'foa> {
+    pub fn new(virtual_interface: &'vif mut VirtualInterface<'foa>) -> Self {
+        let (interface_control, interface_rx_queue) = virtual_interface.split();
+        Self {
+            interface_control,
+            interface_rx_queue,
+        }
+    }
+}
+
+impl DsWiFiRxBuffer for BorrowedBuffer<'_> {
+    fn mpdu_buffer(&self) -> &[u8] {
+        BorrowedBuffer::mpdu_buffer(self)
+    }
+
+    fn rssi(&self) -> i8 {
+        BorrowedBuffer::rssi(self)
+    }
+}
+
+fn hal_rate(rate: WiFiRate) -> esp_wifi_hal::WiFiRate {
+    match rate {
+        WiFiRate::PhyRate1ML => esp_wifi_hal::WiFiRate::PhyRate1ML,
+        WiFiRate::PhyRate2ML => esp_wifi_hal::WiFiRate::PhyRate2ML,
+        WiFiRate::PhyRate5ML => esp_wifi_hal::WiFiRate::PhyRate5ML,
+        WiFiRate::PhyRate11ML => esp_wifi_hal::WiFiRate::PhyRate11ML,
+        WiFiRate::PhyRate2MS => esp_wifi_hal::WiFiRate::PhyRate2MS,
+        WiFiRate::PhyRate5MS => esp_wifi_hal::WiFiRate::PhyRate5MS,
+        WiFiRate::PhyRate11MS => esp_wifi_hal::WiFiRate::PhyRate11MS,
+    }
+}
+
+fn hal_tx_parameters(tx_parameters: &TxParameters) -> esp_wifi_hal::TxParameters {
+    esp_wifi_hal::TxParameters {
+        rate: hal_rate(tx_parameters.rate),
+        duration: tx_parameters.duration,
+        override_seq_num: tx_parameters.override_seq_num,
+        tx_error_behaviour: match tx_parameters.tx_error_behaviour {
+            TxErrorBehaviour::RetryUntil(retries) => esp_wifi_hal::TxErrorBehaviour::RetryUntil(retries as _),
+            TxErrorBehaviour::Drop => esp_wifi_hal::TxErrorBehaviour::Drop,
+        },
+        tx_timeout: tx_parameters.tx_timeout as _,
+    }
+}
+
+fn hal_filter_bank(bank: RxFilterBank) -> esp_wifi_hal::RxFilterBank {
+    match bank {
+        RxFilterBank::BSSID => esp_wifi_hal::RxFilterBank::BSSID,
+        RxFilterBank::ReceiverAddress => esp_wifi_hal::RxFilterBank::ReceiverAddress,
+    }
+}
+
+impl<'foa> DsWiFiRadio for FoARadio<'_, 'foa> {
+    type RxBuffer = BorrowedBuffer<'foa>;
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.interface_control.get_factory_mac_for_interface()
+    }
+
+    async fn alloc_tx_buf(&self) -> impl DerefMut<Target = [u8]> + '_ {
+        self.interface_control.alloc_tx_buf().await
+    }
+
+    async fn transmit(&self, buffer: &mut [u8], tx_parameters: &TxParameters, wait_for_ack: bool) -> Result<(), DsWiFiRadioError> {
+        self.interface_control
+            .transmit(buffer, &hal_tx_parameters(tx_parameters), wait_for_ack)
+            .await
+            .map_err(|_| DsWiFiRadioError::TxFailed)
+    }
+
+    async fn receive(&self) -> Self::RxBuffer {
+        self.interface_rx_queue.receive().await
+    }
+
+    fn lock_channel(&self, channel: u8) -> Result<(), DsWiFiRadioError> {
+        self.interface_control
+            .lock_channel(channel)
+            .map_err(|_| DsWiFiRadioError::ChannelLockFailed)?;
+        unsafe {
+            //workaround for channel setting
+            chip_v7_set_chan_nomac(channel, 0);
+        }
+        Ok(())
+    }
+
+    fn set_filter_parameters(&self, bank: RxFilterBank, mac_address: [u8; 6], mask: Option<[u8; 6]>) {
+        self.interface_control.set_filter_parameters(hal_filter_bank(bank), mac_address, mask);
+    }
+
+    fn set_filter_status(&self, bank: RxFilterBank, enabled: bool) {
+        self.interface_control.set_filter_status(hal_filter_bank(bank), enabled);
+    }
+
+    fn set_tx_power(&self, max_tx_power: i8) {
+        unsafe {
+            //workaround for power cycling
+            phy_set_most_tpw(max_tx_power);
+        }
+    }
+
+    async fn reject_off_channel_request(&self) {
+        self.interface_control.wait_for_off_channel_request().await.reject();
+    }
+}
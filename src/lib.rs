@@ -1,30 +1,37 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(core_intrinsics)]
 #![feature(trivial_bounds)]
 #![feature(slice_pattern)]
 #![feature(future_join)]
 extern crate alloc;
 
+#[macro_use]
+mod fmt;
+
+pub mod airtime;
+pub mod error;
 pub mod runner;
-mod packets;
-mod pictochat_packets;
+pub mod packets;
+pub mod pictochat_packets;
 pub mod pictochat_application;
+pub mod radio;
+pub mod scanner;
+pub mod stats;
+#[cfg(feature = "esp32")]
+pub mod foa_radio;
+pub mod memory_radio;
+#[cfg(feature = "simulator")]
+pub mod simulator;
 
-use core::ffi::c_void;
 use core::future::Future;
 use core::marker::PhantomData;
 use core::ops::{BitAndAssign, BitOrAssign};
-use core::sync::atomic::{AtomicU16, AtomicUsize};
-use defmt::{error, info, warn, Format};
+use core::sync::atomic::{AtomicBool, AtomicI8, AtomicU16, AtomicU32, AtomicU8, AtomicUsize, Ordering};
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::channel::{Channel, DynamicReceiver, DynamicSender};
 use embassy_sync::mutex::Mutex;
 use embassy_sync::signal::Signal;
 use embassy_time::{Duration, Instant, Ticker, Timer};
-use foa::{VirtualInterface};
-use foa::esp_wifi_hal::BorrowedBuffer;
-use foa::esp_wifi_hal::RxFilterBank::{ReceiverAddress, BSSID};
-use foa::lmac::{LMacInterfaceControl};
 use hex_literal::hex;
 use ieee80211::common::{AssociationID, CapabilitiesInformation, DataFrameSubtype, FCFFlags, FrameType, ManagementFrameSubtype, SequenceControl};
 use ieee80211::{element_chain, match_frames, scroll, supported_rates, GenericFrame};
@@ -36,52 +43,83 @@ use ieee80211::mgmt_frame::{BeaconFrame, ManagementFrame, ManagementFrameHeader}
 use ieee80211::mgmt_frame::body::BeaconBody;
 use ieee80211::scroll::ctx::{MeasureWith, TryFromCtx, TryIntoCtx};
 use ieee80211::scroll::Pwrite;
-use crate::packets::{ClientToHostDataFrame};
-use crate::runner::{DsWiFiRunner, PendingDataFrame};
+use crate::packets::{BeaconType, ClientToHostDataFrame, MAX_BEACON_PAYLOAD_SIZE};
+use crate::radio::{DsWiFiRadio, DsWiFiRxBuffer, RxFilterBank};
+use crate::scanner::DsHostInfo;
+use crate::stats::{DsWiFiClientSnapshot, DsWiFiClientStats};
+use crate::error::{DsWiFiClientStateError, DsWiFiControlError, DsWiFiInitError};
+use alloc::vec::Vec;
+use crate::runner::{beacon_interval_tu, is_valid_channel, mp_reply_size_fits, DsWiFiRunner, PendingDataFrame};
 
 pub struct DsWiFiInterface;
 
 const MAX_CLIENTS: usize = 15;
 
+// Control requests go through a single slot, so requesters take turns: the lock is held for the
+// whole round trip. Requests carry an id so a response to a request whose requester was dropped
+// can't be mistaken for the answer to the next one.
 pub struct RequestResponseSignal<Request, Response> {
-    request_signal: Signal<NoopRawMutex, Request>,
-    response_signal: Signal<NoopRawMutex, Response>,
+    next_request_id: Mutex<NoopRawMutex, u32>,
+    current_request_id: AtomicU32,
+    request_signal: Signal<NoopRawMutex, (u32, Request)>,
+    response_signal: Signal<NoopRawMutex, (u32, Response)>,
 }
 
 pub struct Requester<'a, Request, Response> {
-    request_signal: &'a Signal<NoopRawMutex, Request>,
-    response_signal: &'a Signal<NoopRawMutex, Response>,
+    next_request_id: &'a Mutex<NoopRawMutex, u32>,
+    request_signal: &'a Signal<NoopRawMutex, (u32, Request)>,
+    response_signal: &'a Signal<NoopRawMutex, (u32, Response)>,
 }
 
 pub struct Responder<'a, Request, Response> {
-    request_signal: &'a Signal<NoopRawMutex, Request>,
-    response_signal: &'a Signal<NoopRawMutex, Response>,
+    current_request_id: &'a AtomicU32,
+    request_signal: &'a Signal<NoopRawMutex, (u32, Request)>,
+    response_signal: &'a Signal<NoopRawMutex, (u32, Response)>,
 }
 
 impl<'a, Request, Response> Requester<'a, Request, Response> {
     pub async fn send_request_and_wait(&self, request: Request) -> Response {
-        if self.request_signal.signaled() {
-            panic!("Request already sent");
-        }
-        self.request_signal.signal(request);
-        let res = self.response_signal.wait().await;
+        let mut next_request_id = self.next_request_id.lock().await;
+        let request_id = *next_request_id;
+        *next_request_id = next_request_id.wrapping_add(1);
+
         self.response_signal.reset();
-        res
+        self.request_signal.signal((request_id, request));
+        loop {
+            let (response_id, response) = self.response_signal.wait().await;
+            if response_id == request_id {
+                return response;
+            }
+            warn!("dropping response to abandoned request {}", response_id);
+        }
     }
 
 }
 
 impl<'a, Request, Response> Responder<'a, Request, Response> {
     pub async fn wait_for_request(&self) -> Request {
-        self.request_signal.wait().await
+        let (request_id, request) = self.request_signal.wait().await;
+        self.current_request_id.store(request_id, Ordering::Relaxed);
+        request
     }
+    // Id of the request last returned by wait_for_request, for requests that are answered later on.
+    pub fn current_request_id(&self) -> u32 {
+        self.current_request_id.load(Ordering::Relaxed)
+    }
+    // Answers the request last returned by wait_for_request.
     pub fn send_response(&self, response: Response) {
-        self.response_signal.signal(response);
+        self.send_response_to(self.current_request_id(), response);
+    }
+    // Answers a specific request, the response is dropped if its requester gave up in the meantime.
+    pub fn send_response_to(&self, request_id: u32, response: Response) {
+        self.response_signal.signal((request_id, response));
     }
 }
 impl<Request, Response> RequestResponseSignal<Request, Response> {
     pub fn new() -> Self {
         Self {
+            next_request_id: Mutex::new(0),
+            current_request_id: AtomicU32::new(0),
             request_signal: Signal::new(),
             response_signal: Signal::new(),
         }
@@ -89,6 +127,7 @@ impl<Request, Response> RequestResponseSignal<Request, Response> {
 
     pub fn get_requester(&self) -> Requester<Request,Response> {
         Requester {
+            next_request_id: &self.next_request_id,
             request_signal: &self.request_signal,
             response_signal: &self.response_signal,
         }
@@ -96,23 +135,36 @@ impl<Request, Response> RequestResponseSignal<Request, Response> {
 
     pub fn get_responder(&self) -> Responder<Request,Response> {
         Responder {
+            current_request_id: &self.current_request_id,
             request_signal: &self.request_signal,
             response_signal: &self.response_signal,
         }
     }
 }
-extern "C" {
-    pub fn chip_v7_set_chan_nomac(channel: u8, idk: u8);
-    fn phy_set_most_tpw(max_txpwr: i8) -> c_void;
-}
 pub struct DsWiFiClientManager {
     pub clients: [Option<DsWiFiClient>; MAX_CLIENTS],
     pub all_clients_mask: DsWifiClientMask,
     pub current_mask: DsWifiClientMask,
+    pub max_clients: u8,
+    pub client_timeout: Duration,
+    pub auth_timeout: Duration,
+    pub assoc_timeout: Duration,
+    pub leave_timeout: Duration,
 }
 impl DsWiFiClientManager {
+    // How long a client may stay in `state`, DsWiFiClient::timed_out says from when.
+    pub fn state_timeout(&self, state: DsWiFiClientState) -> Duration {
+        match state {
+            DsWiFiClientState::Authenticated => self.auth_timeout,
+            DsWiFiClientState::Associated => self.assoc_timeout,
+            DsWiFiClientState::Connected | DsWiFiClientState::Errored => self.client_timeout,
+            DsWiFiClientState::Leaving => self.leave_timeout,
+        }
+    }
+
+
     pub fn get_next_client_aid(&self) -> Option<AssociationID> {
-        for i in 0..MAX_CLIENTS {
+        for i in 0..self.max_clients as usize {
             if self.clients[i].is_none() {
                 return Some(AssociationID::from((i + 1) as u16));
             }
@@ -144,32 +196,44 @@ impl DsWiFiClientManager {
     pub fn add_client(&mut self, client: DsWiFiClient) {
         let aid = client.association_id;
         self.clients[(aid.aid() - 1) as usize] = Some(client);
-        if client.state == DsWiFiClientState::Connected {
+        if client.state.is_polled() {
             self.all_clients_mask.mask_add(aid.get_mask_bits());
         }
     }
 
-    pub fn update_client_state(&mut self, addr: MACAddress, state: DsWiFiClientState) {
-        {
-            let client = self.get_client_mut(addr).unwrap();
-            client.state = state;
-            client.last_heard_from = Instant::now();
+    pub fn update_client_state(&mut self, addr: MACAddress, state: DsWiFiClientState) -> Result<(), DsWiFiClientStateError> {
+        let client = self.get_client_mut(addr).ok_or(DsWiFiClientStateError::UnknownClient)?;
+        if !client.state.can_transition_to(state) {
+            return Err(DsWiFiClientStateError::InvalidTransition { from: client.state, to: state });
         }
-        let client = self.get_client(addr).unwrap();
-        //todo: sanity check new state is possible given previous state
-        if state == DsWiFiClientState::Connected {
-            self.all_clients_mask.mask_add(client.association_id.get_mask_bits());
+        client.set_state(state);
+
+        let mask_bits = client.association_id.get_mask_bits();
+        if state.is_polled() {
+            self.all_clients_mask.mask_add(mask_bits);
         } else {
-            self.all_clients_mask.mask_subtract(client.association_id.get_mask_bits());
+            self.all_clients_mask.mask_subtract(mask_bits);
         }
-
+        Ok(())
     }
 
     pub fn remove_client(&mut self, aid: AssociationID) {
         self.clients[(aid.aid() - 1) as usize] = None;
         self.all_clients_mask.mask_subtract(aid.get_mask_bits());
+        self.current_mask.mask_subtract(aid.get_mask_bits());
+    }
+
+    // Errored clients aren't in all_clients_mask, the runner still polls them now and then.
+    pub fn errored_clients_mask(&self) -> DsWifiClientMask {
+        self.clients
+            .iter()
+            .flatten()
+            .filter(|client| client.state == DsWiFiClientState::Errored)
+            .fold(0, |mask, client| mask | client.association_id.get_mask_bits())
     }
 }
+pub const CLIENT_HISTORY_LEN: usize = 8;
+
 #[derive(Copy)]
 #[derive(Clone)]
 pub struct DsWiFiClient {
@@ -177,16 +241,138 @@ pub struct DsWiFiClient {
     associated_mac_address: [u8; 6],
     association_id: AssociationID,
     last_heard_from: Instant,
+    state_entered: Instant,
+    consecutive_misses: u8,
+    stats: DsWiFiClientStats,
+    history: [Option<DsWiFiClientTransition>; CLIENT_HISTORY_LEN],
+    history_next: usize,
 }
 impl DsWiFiClient {
+    pub(crate) fn new(associated_mac_address: [u8; 6], association_id: AssociationID) -> Self {
+        let now = Instant::now();
+        Self {
+            state: DsWiFiClientState::Authenticated,
+            associated_mac_address,
+            association_id,
+            last_heard_from: now,
+            state_entered: now,
+            consecutive_misses: 0,
+            stats: DsWiFiClientStats::default(),
+            history: [None; CLIENT_HISTORY_LEN],
+            history_next: 0,
+        }
+    }
+
     pub fn log_client_info(&self) {
         info!("Client: aid: {}, mac: {:?}, state {:?}",self.association_id.aid(),self.associated_mac_address, self.state);
     }
+
+    pub fn state(&self) -> DsWiFiClientState {
+        self.state
+    }
+
+    pub fn mac_address(&self) -> [u8; 6] {
+        self.associated_mac_address
+    }
+
+    pub fn association_id(&self) -> AssociationID {
+        self.association_id
+    }
+
+    // MP polls missed in a row, reset by every reply.
+    pub fn consecutive_misses(&self) -> u8 {
+        self.consecutive_misses
+    }
+
+    pub fn stats(&self) -> &DsWiFiClientStats {
+        &self.stats
+    }
+
+    pub fn snapshot(&self) -> DsWiFiClientSnapshot {
+        let mut history = [None; CLIENT_HISTORY_LEN];
+        for (slot, transition) in history.iter_mut().zip(self.history()) {
+            *slot = Some(*transition);
+        }
+        DsWiFiClientSnapshot {
+            mac_address: self.associated_mac_address,
+            association_id: self.association_id.aid(),
+            state: self.state,
+            last_heard: self.last_heard_from,
+            stats: self.stats,
+            history,
+        }
+    }
+
+    // The last few state changes of this client, oldest first.
+    pub fn history(&self) -> impl Iterator<Item = &DsWiFiClientTransition> {
+        let (newer, older) = self.history.split_at(self.history_next);
+        older.iter().chain(newer.iter()).flatten()
+    }
+
+    // States waiting for the console to do something time out from when they were entered,
+    // the others from when the console was last heard from.
+    pub(crate) fn timed_out(&self, timeout: Duration) -> bool {
+        let since = match self.state {
+            DsWiFiClientState::Authenticated | DsWiFiClientState::Associated | DsWiFiClientState::Leaving => self.state_entered,
+            DsWiFiClientState::Connected | DsWiFiClientState::Errored => self.last_heard_from,
+        };
+        since.elapsed() > timeout
+    }
+
+    fn set_state(&mut self, state: DsWiFiClientState) {
+        let now = Instant::now();
+        self.history[self.history_next] = Some(DsWiFiClientTransition {
+            from: self.state,
+            to: state,
+            at: now,
+        });
+        self.history_next = (self.history_next + 1) % CLIENT_HISTORY_LEN;
+        self.state = state;
+        self.state_entered = now;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DsWiFiClientTransition {
+    pub from: DsWiFiClientState,
+    pub to: DsWiFiClientState,
+    pub at: Instant,
 }
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum DsWiFiClientState {
-    Associating,
+    // authentication accepted, waiting for the association request
+    Authenticated,
+    // association accepted, polled but hasn't replied yet
+    Associated,
     Connected,
+    // stopped replying to polls. Errored clients drop out of the poll mask and only get polled every
+    // few frames, a reply brings them back to Connected, otherwise they time out.
+    Errored,
+    // being deauthenticated, about to be removed
+    Leaving,
+}
+
+impl DsWiFiClientState {
+    pub fn can_transition_to(&self, next: DsWiFiClientState) -> bool {
+        use DsWiFiClientState::*;
+        match (*self, next) {
+            (Authenticated, Associated) => true,
+            (Associated, Connected | Errored) => true,
+            (Connected, Errored) => true,
+            (Errored, Connected) => true,
+            (Leaving, _) => false,
+            (_, Leaving) => true,
+            _ => false,
+        }
+    }
+
+    // Whether clients in this state are included in the MP poll mask.
+    pub fn is_polled(&self) -> bool {
+        matches!(self, DsWiFiClientState::Associated | DsWiFiClientState::Connected)
+    }
 }
 pub type DsWifiClientMask = u16;
 pub trait DsWifiClientMaskMath {
@@ -224,46 +410,171 @@ impl DsWifiAidClientMaskBits for AssociationID {
 
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DsWiFiRetryLimits {
+    // how often a data frame is resent to the clients that didn't answer before moving on
+    pub max_frame_retries: u8,
+    // polls a client can miss in a row before it's marked as errored and no longer polled
+    pub max_consecutive_misses: u8,
+}
+
+impl Default for DsWiFiRetryLimits {
+    fn default() -> Self {
+        Self {
+            max_frame_retries: 4,
+            max_consecutive_misses: 4,
+        }
+    }
+}
+
+// The game specific part of the beacon's Nintendo vendor element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DsWiFiBeaconContents {
+    pub game_id: [u8; 4],
+    pub beacon_type: BeaconType,
+    pub cmd_data_size: u16,
+    pub reply_data_size: u16,
+    pub payload: Vec<u8>,
+    // byte of the payload the runner keeps set to the number of consoles, host included
+    pub client_count_offset: Option<usize>,
+}
+
+impl DsWiFiBeaconContents {
+    pub fn new(game_id: [u8; 4]) -> Self {
+        Self {
+            game_id,
+            beacon_type: BeaconType::MULTICART,
+            cmd_data_size: 0x00c0,
+            reply_data_size: 0x00c0,
+            payload: Vec::new(),
+            client_count_offset: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), DsWiFiControlError> {
+        if self.payload.len() > MAX_BEACON_PAYLOAD_SIZE {
+            return Err(DsWiFiControlError::InvalidParameter("beacon payload"));
+        }
+        if self.client_count_offset.is_some_and(|offset| offset >= self.payload.len()) {
+            return Err(DsWiFiControlError::InvalidParameter("client count offset"));
+        }
+        if !mp_reply_size_fits(self.reply_data_size) {
+            return Err(DsWiFiControlError::InvalidParameter("reply data size"));
+        }
+        Ok(())
+    }
+}
+
 pub enum DsWiFiInterfaceControlEvent {
     SetChannel(u8),
     SetBeaconsEnabled(bool),
-    
+    SetRetryLimits(DsWiFiRetryLimits),
+    // answered with Clients
+    ListClients,
+    // deauthenticates the client with this mac address, fails if it isn't connected
+    KickClient([u8; 6]),
+    SetTxPower(i8),
+    SetBeaconInterval(Duration),
+    SetMpPollInterval(Duration),
+    // replaces what the beacons advertise, the runner only fills in the live client count
+    SetBeaconContents(DsWiFiBeaconContents),
+    // when closed, new consoles get refused, connected ones stay
+    SetAcceptingClients(bool),
+    // answered with Stats
+    GetStats,
+    // stops hosting, listens on every DS channel for the given dwell time and answers with ScanResults,
+    // refused while clients are connected since they'd time out while we're off channel
+    ScanForHosts(Duration),
 }
 
 pub enum DsWiFiInterfaceControlEventResponse {
-    Failed,
+    Failed(DsWiFiControlError),
     Success,
+    ScanResults(Vec<DsHostInfo>),
+    Clients(Vec<DsWiFiClientSnapshot>),
+    Stats(DsWiFiInterfaceStats),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DsWiFiInterfaceStats {
+    pub channel: u8,
+    pub tx_power: i8,
+    pub beacon_interval: Duration,
+    pub mp_poll_interval: Duration,
+    pub accepting_clients: bool,
+    pub client_count: u8,
+    pub dropped_frames: usize,
+    pub data_seq: u16,
 }
 
 pub enum DsWiFiClientEvent {
     Disconnected([u8; 6]),
     Connected([u8; 6]),
+    // the client didn't answer the poll for the data frame with this sequence number
+    FrameMissed([u8; 6], u16),
 }
 
-pub struct DsWiFiSharedResources<'res> {
-    client_manager: Mutex<NoopRawMutex, DsWiFiClientManager>,
+// A client reply payload, still sitting in the rx buffer it was received in. The buffer goes back
+// to the radio once this is dropped, so don't hold on to it for longer than needed.
+pub struct DsWiFiReceivedData<Rx: DsWiFiRxBuffer> {
+    pub source: MACAddress,
+    buffer: Rx,
+    // where the client reply starts in the mpdu
+    reply_offset: usize,
+}
+
+impl<Rx: DsWiFiRxBuffer> DsWiFiReceivedData<Rx> {
+    pub(crate) fn new(source: MACAddress, buffer: Rx, reply_offset: usize) -> Self {
+        Self {
+            source,
+            buffer,
+            reply_offset,
+        }
+    }
 
-    interface_control: Option<LMacInterfaceControl<'res>>,
+    pub fn payload(&self) -> &[u8] {
+        // the runner only queues replies that parsed and carry a payload
+        ClientToHostDataFrame::try_from_ctx(&self.buffer.mpdu_buffer()[self.reply_offset..], ())
+            .ok()
+            .and_then(|(reply, _)| reply.payload)
+            .unwrap_or(&[])
+    }
 
-    bg_rx_queue: Channel<NoopRawMutex, BorrowedBuffer<'res>, 4>,
+    pub fn rssi(&self) -> i8 {
+        self.buffer.rssi()
+    }
+}
+
+pub struct DsWiFiSharedResources<R: DsWiFiRadio> {
+    client_manager: Mutex<NoopRawMutex, DsWiFiClientManager>,
+
+    bg_rx_queue: Channel<NoopRawMutex, R::RxBuffer, 4>,
     ack_rx_queue: Channel<NoopRawMutex, (MACAddress, Instant), 4>,
 
     data_tx_mutex: Mutex<NoopRawMutex,PendingDataFrame>,
-    data_queue: Channel<NoopRawMutex, ([u8;300], MACAddress, u16), 4>,
+    // every entry holds one of the radio's rx buffers
+    data_queue: Channel<NoopRawMutex, DsWiFiReceivedData<R::RxBuffer>, 2>,
     data_tx_signal: Signal<NoopRawMutex, DsWiFiControlEvent>,
     data_tx_signal_2: Signal<NoopRawMutex, DsWiFiControlEvent>,
     control_channel: RequestResponseSignal<DsWiFiInterfaceControlEvent, DsWiFiInterfaceControlEventResponse>,
     client_queue: Channel<NoopRawMutex, DsWiFiClientEvent, 4>,
+    dropped_frames: AtomicUsize,
 }
 
-impl Default for DsWiFiSharedResources<'_> {
+impl<R: DsWiFiRadio> Default for DsWiFiSharedResources<R> {
     fn default() -> Self {
         Self {
             client_manager: Mutex::from(DsWiFiClientManager {
                 clients: [None; MAX_CLIENTS],
                 all_clients_mask: 0x0000,
                 current_mask: 0,
-
+                max_clients: MAX_CLIENTS as u8,
+                client_timeout: Duration::from_secs(1),
+                auth_timeout: Duration::from_secs(1),
+                assoc_timeout: Duration::from_secs(2),
+                leave_timeout: Duration::from_millis(100),
             }),
             bg_rx_queue: Channel::new(),
             ack_rx_queue: Channel::new(),
@@ -271,13 +582,14 @@ impl Default for DsWiFiSharedResources<'_> {
                 data: [0;300],
                 flags: Default::default(),
                 size: 0,
+                retries: 0,
             }),
             data_queue: Channel::new(),
             data_tx_signal: Signal::new(),
-            interface_control: None,
             data_tx_signal_2: Signal::new(),
             control_channel: RequestResponseSignal::new(),
             client_queue: Channel::new(),
+            dropped_frames: AtomicUsize::new(0),
         }
     }
 }
@@ -286,16 +598,98 @@ pub enum DsWiFiControlEvent {
     FrameRequired,
     FrameGenerated,
 }
-pub struct DsWiFiControl<'res> {
-    pub data_rx: DynamicReceiver<'res,([u8;300], MACAddress, u16)>,
+pub struct DsWiFiControl<'res, R: DsWiFiRadio> {
+    // Client reply payloads. Each one keeps a radio rx buffer until it's dropped and the radio
+    // only has a few of them, so handle the data as it comes in instead of collecting it.
+    pub data_rx: DynamicReceiver<'res, DsWiFiReceivedData<R::RxBuffer>>,
     pub data_tx_mutex: &'res Mutex<NoopRawMutex, PendingDataFrame>,
     pub data_tx_signal: &'res Signal<NoopRawMutex, DsWiFiControlEvent>,
     pub data_tx_signal_2: &'res Signal<NoopRawMutex, DsWiFiControlEvent>,
     pub control_requester: Requester<'res, DsWiFiInterfaceControlEvent,DsWiFiInterfaceControlEventResponse>,
-    pub client_manager: &'res Mutex<NoopRawMutex, DsWiFiClientManager>,
+    pub(crate) client_manager: &'res Mutex<NoopRawMutex, DsWiFiClientManager>,
     pub event_rx: DynamicReceiver<'res,DsWiFiClientEvent>,
     pub mac_address: [u8; 6],
+    dropped_frames: &'res AtomicUsize,
+}
+
+impl<R: DsWiFiRadio> DsWiFiControl<'_, R> {
+    async fn request(&self, request: DsWiFiInterfaceControlEvent) -> Result<DsWiFiInterfaceControlEventResponse, DsWiFiControlError> {
+        match self.control_requester.send_request_and_wait(request).await {
+            DsWiFiInterfaceControlEventResponse::Failed(e) => Err(e),
+            response => Ok(response),
+        }
+    }
+
+    async fn request_success(&self, request: DsWiFiInterfaceControlEvent) -> Result<(), DsWiFiControlError> {
+        match self.request(request).await? {
+            DsWiFiInterfaceControlEventResponse::Success => Ok(()),
+            _ => Err(DsWiFiControlError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn set_channel(&self, channel: u8) -> Result<(), DsWiFiControlError> {
+        self.request_success(DsWiFiInterfaceControlEvent::SetChannel(channel)).await
+    }
+
+    pub async fn set_beacons_enabled(&self, enabled: bool) -> Result<(), DsWiFiControlError> {
+        self.request_success(DsWiFiInterfaceControlEvent::SetBeaconsEnabled(enabled)).await
+    }
+
+    pub async fn set_retry_limits(&self, retry_limits: DsWiFiRetryLimits) -> Result<(), DsWiFiControlError> {
+        self.request_success(DsWiFiInterfaceControlEvent::SetRetryLimits(retry_limits)).await
+    }
+
+    // Copies the state and link statistics of every client.
+    pub async fn list_clients(&self) -> Result<Vec<DsWiFiClientSnapshot>, DsWiFiControlError> {
+        match self.request(DsWiFiInterfaceControlEvent::ListClients).await? {
+            DsWiFiInterfaceControlEventResponse::Clients(clients) => Ok(clients),
+            _ => Err(DsWiFiControlError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn kick_client(&self, mac_address: [u8; 6]) -> Result<(), DsWiFiControlError> {
+        self.request_success(DsWiFiInterfaceControlEvent::KickClient(mac_address)).await
+    }
+
+    pub async fn set_tx_power(&self, tx_power: i8) -> Result<(), DsWiFiControlError> {
+        self.request_success(DsWiFiInterfaceControlEvent::SetTxPower(tx_power)).await
+    }
 
+    pub async fn set_beacon_interval(&self, interval: Duration) -> Result<(), DsWiFiControlError> {
+        self.request_success(DsWiFiInterfaceControlEvent::SetBeaconInterval(interval)).await
+    }
+
+    pub async fn set_mp_poll_interval(&self, interval: Duration) -> Result<(), DsWiFiControlError> {
+        self.request_success(DsWiFiInterfaceControlEvent::SetMpPollInterval(interval)).await
+    }
+
+    pub async fn set_beacon_contents(&self, contents: DsWiFiBeaconContents) -> Result<(), DsWiFiControlError> {
+        self.request_success(DsWiFiInterfaceControlEvent::SetBeaconContents(contents)).await
+    }
+
+    pub async fn set_accepting_clients(&self, accepting: bool) -> Result<(), DsWiFiControlError> {
+        self.request_success(DsWiFiInterfaceControlEvent::SetAcceptingClients(accepting)).await
+    }
+
+    pub async fn get_stats(&self) -> Result<DsWiFiInterfaceStats, DsWiFiControlError> {
+        match self.request(DsWiFiInterfaceControlEvent::GetStats).await? {
+            DsWiFiInterfaceControlEventResponse::Stats(stats) => Ok(stats),
+            _ => Err(DsWiFiControlError::UnexpectedResponse),
+        }
+    }
+
+    pub async fn scan_for_hosts(&self, dwell_time: Duration) -> Result<Vec<DsHostInfo>, DsWiFiControlError> {
+        match self.request(DsWiFiInterfaceControlEvent::ScanForHosts(dwell_time)).await? {
+            DsWiFiInterfaceControlEventResponse::ScanResults(hosts) => Ok(hosts),
+            _ => Err(DsWiFiControlError::UnexpectedResponse),
+        }
+    }
+
+    // Number of received frames the runner had to throw away, either because they were malformed
+    // or because the application didn't keep up with the data queue.
+    pub fn dropped_frames(&self) -> usize {
+        self.dropped_frames.load(Ordering::Relaxed)
+    }
 }
 
 /*
@@ -362,39 +756,124 @@ impl<'res> DsWiFiInput<'res, > {
 }
 */
 
-pub struct DsWiFiInitInfo {}
+pub struct DsWiFiInitInfo {
+    pub channel: u8,
+    // when set, the runner listens this long on each DS channel before hosting and picks the
+    // quietest one instead of `channel`
+    pub auto_channel_dwell_time: Option<Duration>,
+    pub tx_power: i8,
+    // uses the radio's own address when None
+    pub mac_address: Option<[u8; 6]>,
+    pub beacon_interval: Duration,
+    // every this many beacons is a DTIM beacon
+    pub dtim_period: u8,
+    pub mp_poll_interval: Duration,
+    // how long a connected client may stay silent before it's dropped
+    pub client_timeout: Duration,
+    // how long a console gets to associate after authenticating
+    pub auth_timeout: Duration,
+    // how long an associated console gets to answer its first poll
+    pub assoc_timeout: Duration,
+    // how long a client that is being deauthenticated is kept around
+    pub leave_timeout: Duration,
+    pub max_clients: u8,
+    pub game_id: [u8; 4],
+    // identifies this hosting session, every beacon of the session carries the same value
+    pub stream_code: u16,
+    pub retry_limits: DsWiFiRetryLimits,
+}
 
 impl Default for DsWiFiInitInfo {
     fn default() -> Self {
-        Self {}
+        Self {
+            channel: 7,
+            auto_channel_dwell_time: None,
+            tx_power: 20,
+            mac_address: None,
+            beacon_interval: Duration::from_millis(100),
+            dtim_period: 2,
+            mp_poll_interval: Duration::from_millis(33), //very slow rate limit for now
+            client_timeout: Duration::from_secs(1),
+            auth_timeout: Duration::from_secs(1),
+            assoc_timeout: Duration::from_secs(2),
+            leave_timeout: Duration::from_millis(100),
+            max_clients: MAX_CLIENTS as u8,
+            game_id: [0x00, 0x00, 0x00, 0x00],
+            stream_code: 0x0f0f,
+            retry_limits: DsWiFiRetryLimits::default(),
+        }
     }
 }
 
-pub fn new_ds_wifi_interface<'vif, 'foa>(
-    virtual_interface: &'vif mut VirtualInterface<'foa>,
-    shared_resources: &'vif mut DsWiFiSharedResources<'foa>) -> (
-    DsWiFiControl<'vif>,
-    DsWiFiRunner<'vif, 'foa>,
-    )
-{
-    let (interface_control, interface_rx_queue) = virtual_interface.split();
-    let mac_address = interface_control.get_factory_mac_for_interface();
-
-    interface_control.lock_channel(7).expect("TODO: panic message");
-    unsafe {
-        //workaround for power cycling
-        phy_set_most_tpw(20);
+impl DsWiFiInitInfo {
+    pub fn validate(&self) -> Result<(), DsWiFiInitError> {
+        if !is_valid_channel(self.channel) {
+            return Err(DsWiFiInitError::InvalidParameter("channel"));
+        }
+        if self.max_clients == 0 || self.max_clients as usize > MAX_CLIENTS {
+            return Err(DsWiFiInitError::InvalidParameter("max_clients"));
+        }
+        if beacon_interval_tu(self.beacon_interval).is_none() {
+            return Err(DsWiFiInitError::InvalidParameter("beacon_interval"));
+        }
+        if self.dtim_period == 0 {
+            return Err(DsWiFiInitError::InvalidParameter("dtim_period"));
+        }
+        if self.auto_channel_dwell_time.is_some_and(|dwell_time| dwell_time.as_ticks() == 0) {
+            return Err(DsWiFiInitError::InvalidParameter("auto_channel_dwell_time"));
+        }
+        if self.mp_poll_interval.as_ticks() == 0 {
+            return Err(DsWiFiInitError::InvalidParameter("mp_poll_interval"));
+        }
+        if self.client_timeout.as_ticks() == 0 {
+            return Err(DsWiFiInitError::InvalidParameter("client_timeout"));
+        }
+        if self.auth_timeout.as_ticks() == 0 {
+            return Err(DsWiFiInitError::InvalidParameter("auth_timeout"));
+        }
+        if self.assoc_timeout.as_ticks() == 0 {
+            return Err(DsWiFiInitError::InvalidParameter("assoc_timeout"));
+        }
+        if self.leave_timeout.as_ticks() == 0 {
+            return Err(DsWiFiInitError::InvalidParameter("leave_timeout"));
+        }
+        if self.retry_limits.max_frame_retries == 0 || self.retry_limits.max_consecutive_misses == 0 {
+            return Err(DsWiFiInitError::InvalidParameter("retry_limits"));
+        }
+        Ok(())
+    }
+}
 
-        //workaround for channel setting
-        chip_v7_set_chan_nomac(7,0);
+pub fn new_ds_wifi_interface<'vif, R: DsWiFiRadio>(
+    radio: &'vif R,
+    shared_resources: &'vif mut DsWiFiSharedResources<R>,
+    init_info: DsWiFiInitInfo) -> Result<(
+    DsWiFiControl<'vif, R>,
+    DsWiFiRunner<'vif, R>,
+    ), DsWiFiInitError>
+{
+    init_info.validate()?;
+    let mac_address = init_info.mac_address.unwrap_or_else(|| radio.mac_address());
+
+    radio.lock_channel(init_info.channel)?;
+    radio.set_tx_power(init_info.tx_power);
+
+    {
+        let client_manager = shared_resources.client_manager.get_mut();
+        client_manager.max_clients = init_info.max_clients;
+        client_manager.client_timeout = init_info.client_timeout;
+        client_manager.auth_timeout = init_info.auth_timeout;
+        client_manager.assoc_timeout = init_info.assoc_timeout;
+        client_manager.leave_timeout = init_info.leave_timeout;
     }
-    interface_control.set_filter_parameters(BSSID,mac_address,None);
-    interface_control.set_filter_parameters(ReceiverAddress,mac_address,Some([0x00;6]));
 
-    interface_control.set_filter_status(BSSID,true);
-    interface_control.set_filter_status(ReceiverAddress,true);
+    radio.set_filter_parameters(RxFilterBank::BSSID,mac_address,None);
+    radio.set_filter_parameters(RxFilterBank::ReceiverAddress,mac_address,Some([0x00;6]));
 
-    (
+    radio.set_filter_status(RxFilterBank::BSSID,true);
+    radio.set_filter_status(RxFilterBank::ReceiverAddress,true);
+
+    Ok((
         DsWiFiControl {
             data_rx: shared_resources.data_queue.dyn_receiver(),
             data_tx_mutex: &shared_resources.data_tx_mutex,
@@ -403,10 +882,11 @@ pub fn new_ds_wifi_interface<'vif, 'foa>(
             control_requester: shared_resources.control_channel.get_requester(),
             client_manager: &shared_resources.client_manager,
             event_rx: shared_resources.client_queue.dyn_receiver(),
-            mac_address
+            mac_address,
+            dropped_frames: &shared_resources.dropped_frames,
         },
         DsWiFiRunner {
-            interface_control,
+            radio,
             client_manager: &shared_resources.client_manager,
             mac_address,
             bg_rx_queue: shared_resources.bg_rx_queue.dyn_receiver(),
@@ -417,14 +897,29 @@ pub fn new_ds_wifi_interface<'vif, 'foa>(
             data_tx_signal_2: &shared_resources.data_tx_signal_2,
             control_responder: shared_resources.control_channel.get_responder(),
             beacons_enabled: Mutex::from(false),
+            beacon_interval: Mutex::from(init_info.beacon_interval),
+            mp_poll_interval: Mutex::from(init_info.mp_poll_interval),
+            accepting_clients: AtomicBool::new(true),
+            tx_power: AtomicI8::new(init_info.tx_power),
+            beacon_contents: Mutex::from(DsWiFiBeaconContents::new(init_info.game_id)),
+            stream_code: init_info.stream_code,
+            dtim_period: init_info.dtim_period,
+            dtim_count: AtomicU8::new(0),
+            mgmt_seq_num: AtomicU16::new(0),
+            data_seq_num: AtomicU16::new(0),
+            channel: AtomicU8::new(init_info.channel),
+            auto_channel_dwell_time: init_info.auto_channel_dwell_time,
+            scan: Mutex::from(None),
             event_tx: shared_resources.client_queue.dyn_sender(),
             data_seq: AtomicU16::new(0),
-            interface_rx_queue,
+            mp_poll_count: AtomicU8::new(0),
+            retry_limits: Mutex::from(init_info.retry_limits),
             bg_rx_queue_sender: shared_resources.bg_rx_queue.dyn_sender(),
             ack_rx_queue_sender: shared_resources.ack_rx_queue.dyn_sender(),
             data_rx_queue_sender: shared_resources.data_queue.dyn_sender(),
+            dropped_frames: &shared_resources.dropped_frames,
         }
-    )
+    ))
 }
 /*
 impl Interface for DsWiFiInterface {
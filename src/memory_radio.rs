@@ -0,0 +1,164 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::ops::DerefMut;
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_time::Instant;
+use crate::radio::{DsWiFiRadio, DsWiFiRadioError, DsWiFiRxBuffer, RxFilterBank, TxParameters};
+
+// A radio backend that keeps everything in memory, frames "transmitted" by the runner end up in
+// the tx queue and frames injected with `inject_rx` are handed to the runner as if received.
+
+const MEMORY_TX_BUFFER_SIZE: usize = 1600;
+const MEMORY_RX_QUEUE_SIZE: usize = 16;
+const MEMORY_TX_QUEUE_SIZE: usize = 32;
+
+pub struct MemoryRxBuffer {
+    pub data: Vec<u8>,
+    pub rssi: i8,
+}
+
+impl DsWiFiRxBuffer for MemoryRxBuffer {
+    fn mpdu_buffer(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    fn rssi(&self) -> i8 {
+        self.rssi
+    }
+}
+
+pub struct MemoryTxFrame {
+    pub data: Vec<u8>,
+    pub tx_parameters: TxParameters,
+    pub wait_for_ack: bool,
+    pub timestamp: Instant,
+}
+
+#[derive(Clone, Copy)]
+pub struct MemoryRxFilter {
+    pub mac_address: [u8; 6],
+    pub mask: Option<[u8; 6]>,
+    pub enabled: bool,
+}
+
+pub struct MemoryRadio {
+    mac_address: [u8; 6],
+    channel: Cell<u8>,
+    tx_power: Cell<i8>,
+    filters: Cell<[MemoryRxFilter; 2]>,
+    tx_result: Cell<Result<(), DsWiFiRadioError>>,
+    rx_queue: Channel<NoopRawMutex, MemoryRxBuffer, MEMORY_RX_QUEUE_SIZE>,
+    tx_queue: Channel<NoopRawMutex, MemoryTxFrame, MEMORY_TX_QUEUE_SIZE>,
+}
+
+impl MemoryRadio {
+    pub fn new(mac_address: [u8; 6]) -> Self {
+        let filter = MemoryRxFilter {
+            mac_address: [0x00; 6],
+            mask: None,
+            enabled: false,
+        };
+        Self {
+            mac_address,
+            channel: Cell::new(0),
+            tx_power: Cell::new(0),
+            filters: Cell::new([filter; 2]),
+            tx_result: Cell::new(Ok(())),
+            rx_queue: Channel::new(),
+            tx_queue: Channel::new(),
+        }
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.channel.get()
+    }
+
+    pub fn tx_power(&self) -> i8 {
+        self.tx_power.get()
+    }
+
+    pub fn filter(&self, bank: RxFilterBank) -> MemoryRxFilter {
+        self.filters.get()[bank as usize]
+    }
+
+    // Every following transmission will return this result, use it to simulate missing acks.
+    pub fn set_tx_result(&self, result: Result<(), DsWiFiRadioError>) {
+        self.tx_result.set(result);
+    }
+
+    pub fn inject_rx(&self, frame: &[u8], rssi: i8) -> bool {
+        self.rx_queue.try_send(MemoryRxBuffer {
+            data: frame.to_vec(),
+            rssi,
+        }).is_ok()
+    }
+
+    pub fn try_take_tx(&self) -> Option<MemoryTxFrame> {
+        self.tx_queue.try_receive().ok()
+    }
+
+    pub async fn take_tx(&self) -> MemoryTxFrame {
+        self.tx_queue.receive().await
+    }
+}
+
+impl DsWiFiRadio for MemoryRadio {
+    type RxBuffer = MemoryRxBuffer;
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac_address
+    }
+
+    async fn alloc_tx_buf(&self) -> impl DerefMut<Target = [u8]> + '_ {
+        vec![0u8; MEMORY_TX_BUFFER_SIZE]
+    }
+
+    async fn transmit(&self, buffer: &mut [u8], tx_parameters: &TxParameters, wait_for_ack: bool) -> Result<(), DsWiFiRadioError> {
+        let frame = MemoryTxFrame {
+            data: buffer.to_vec(),
+            tx_parameters: *tx_parameters,
+            wait_for_ack,
+            timestamp: Instant::now(),
+        };
+        if self.tx_queue.try_send(frame).is_err() {
+            // nobody is draining the queue, treat it like the frame got lost on air
+            return Err(DsWiFiRadioError::TxFailed);
+        }
+        self.tx_result.get()
+    }
+
+    async fn receive(&self) -> Self::RxBuffer {
+        self.rx_queue.receive().await
+    }
+
+    fn lock_channel(&self, channel: u8) -> Result<(), DsWiFiRadioError> {
+        if !(1..=14).contains(&channel) {
+            return Err(DsWiFiRadioError::ChannelLockFailed);
+        }
+        self.channel.set(channel);
+        Ok(())
+    }
+
+    fn set_filter_parameters(&self, bank: RxFilterBank, mac_address: [u8; 6], mask: Option<[u8; 6]>) {
+        let mut filters = self.filters.get();
+        filters[bank as usize].mac_address = mac_address;
+        filters[bank as usize].mask = mask;
+        self.filters.set(filters);
+    }
+
+    fn set_filter_status(&self, bank: RxFilterBank, enabled: bool) {
+        let mut filters = self.filters.get();
+        filters[bank as usize].enabled = enabled;
+        self.filters.set(filters);
+    }
+
+    fn set_tx_power(&self, max_tx_power: i8) {
+        self.tx_power.set(max_tx_power);
+    }
+
+    async fn reject_off_channel_request(&self) {
+        core::future::pending::<()>().await
+    }
+}
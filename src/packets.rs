@@ -1,11 +1,25 @@
 use bitflags::{bitflags, Flags};
-use defmt::error;
 use embedded_io_async::Read;
 use ieee80211::scroll;
 use ieee80211::scroll::ctx::{MeasureWith, TryFromCtx, TryIntoCtx};
 use ieee80211::scroll::{Endian, Pread, Pwrite};
 use ieee80211::scroll::Endian::Little;
 use crate::DsWifiClientMask;
+use crate::error::DsWiFiPacketError;
+
+pub const NINTENDO_OUI: [u8; 3] = [0x00, 0x09, 0xbf];
+const VENDOR_SPECIFIC_ELEMENT_ID: u8 = 221;
+// an element body is at most 255 bytes, minus the OUI and the fixed part of the tag
+pub const MAX_BEACON_PAYLOAD_SIZE: usize = 255 - 3 - 21;
+
+// Reads a payload whose size came from a length field, so a short buffer is reported as a bad
+// length instead of a plain truncation.
+pub(crate) fn read_payload<'a>(from: &'a [u8], offset: &mut usize, size: usize, field: &'static str) -> Result<&'a [u8], DsWiFiPacketError> {
+    let payload = from.get(*offset..*offset + size)
+        .ok_or(DsWiFiPacketError::BadLength { field, value: size })?;
+    *offset += size;
+    Ok(payload)
+}
 
 pub struct DSWiFiBeaconTag<Payload: TryIntoCtx<()> + MeasureWith<()>> {
     pub oui_type: u8,
@@ -85,7 +99,69 @@ impl<Payload: TryIntoCtx + AsRef<[u8]>> Default for DSWiFiBeaconTag<Payload> {
         }
     }
 }
-#[derive(Clone, Copy)]
+impl<'a> TryFromCtx<'a, ()> for DSWiFiBeaconTag<&'a [u8]> {
+    type Error = DsWiFiPacketError;
+
+    // `from` is the vendor element body after the Nintendo OUI
+    fn try_from_ctx(from: &'a [u8], _: ()) -> Result<(Self, usize), Self::Error> {
+        let mut offset = 0;
+
+        let oui_type: u8 = from.gread_with(&mut offset, Little)?;
+        let stepping_offset: [u8; 2] = from.gread_with(&mut offset, Little)?;
+        let lcd_video_sync: [u8; 2] = from.gread_with(&mut offset, Little)?;
+        let fixed_id: [u8; 4] = from.gread_with(&mut offset, Little)?;
+        let game_id: [u8; 4] = from.gread_with(&mut offset, Little)?;
+        let stream_code: u16 = from.gread_with(&mut offset, Little)?;
+        let payload_size: u8 = from.gread_with(&mut offset, Little)?;
+        let beacon_type_raw: u8 = from.gread_with(&mut offset, Little)?;
+        let beacon_type = BeaconType::try_from(beacon_type_raw)?;
+        let cmd_data_size: u16 = from.gread_with(&mut offset, Little)?;
+        let reply_data_size: u16 = from.gread_with(&mut offset, Little)?;
+        if payload_size as usize > MAX_BEACON_PAYLOAD_SIZE {
+            return Err(DsWiFiPacketError::OversizePayload { size: payload_size as usize, max: MAX_BEACON_PAYLOAD_SIZE });
+        }
+        let payload = if payload_size > 0 {
+            let payload: &[u8] = read_payload(from, &mut offset, payload_size as usize, "payload_size")?;
+            Some(payload)
+        } else { None };
+
+        Ok((Self {
+            oui_type,
+            stepping_offset,
+            lcd_video_sync,
+            fixed_id,
+            game_id,
+            stream_code,
+            beacon_type,
+            cmd_data_size,
+            reply_data_size,
+            payload,
+        }, offset))
+    }
+}
+
+impl<'a> DSWiFiBeaconTag<&'a [u8]> {
+    // Looks for the Nintendo vendor element in the raw elements of a beacon body and parses it.
+    pub fn find_in_elements(elements: &'a [u8]) -> Option<Self> {
+        let (_, body) = raw_elements(elements)
+            .find(|(element_id, body)| *element_id == VENDOR_SPECIFIC_ELEMENT_ID && body.starts_with(&NINTENDO_OUI))?;
+        body.pread_with::<Self>(NINTENDO_OUI.len(), ()).ok()
+    }
+}
+
+// Walks (element id, element body) pairs of a raw element chain, stops at the first truncated element.
+pub(crate) fn raw_elements(elements: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut offset = 0;
+    core::iter::from_fn(move || {
+        let element_id = *elements.get(offset)?;
+        let element_length = *elements.get(offset + 1)? as usize;
+        let body = elements.get(offset + 2..offset + 2 + element_length)?;
+        offset += 2 + element_length;
+        Some((element_id, body))
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum BeaconType {
     MULTICART = 0x01,
@@ -93,10 +169,76 @@ pub enum BeaconType {
     MULTIBOOT = 0x0b,
 }
 
-pub struct ClientToHostDataFrame {
+impl TryFrom<u8> for BeaconType {
+    type Error = DsWiFiPacketError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(BeaconType::MULTICART),
+            0x09 => Ok(BeaconType::EMPTY),
+            0x0b => Ok(BeaconType::MULTIBOOT),
+            _ => Err(DsWiFiPacketError::UnknownType { field: "beacon_type", value: value as u16 }),
+        }
+    }
+}
+
+// Body of the Traffic Indication Map element (IEEE 802.11 9.4.2.5). Bit n of the traffic mask is
+// set when the client with AID n has MP data waiting, the same layout as the client masks. The
+// MP frames themselves are multicast, which is announced through the group bit on DTIM beacons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrafficIndicationMap {
+    pub dtim_count: u8,
+    pub dtim_period: u8,
+    pub group_traffic: bool,
+    pub traffic_mask: DsWifiClientMask,
+}
+
+impl TrafficIndicationMap {
+    pub const ELEMENT_ID: u8 = 5;
+    // dtim count, dtim period, bitmap control and a virtual bitmap large enough for every AID
+    pub const MAX_SIZE: usize = 3 + size_of::<DsWifiClientMask>();
+
+    // Returns the bitmap control byte and the part of the virtual bitmap between the first and
+    // last octet that has a bit set. The offset has to be even, so the range may start one
+    // octet early, and an empty bitmap is still sent as a single zero octet.
+    fn partial_virtual_bitmap(&self) -> (u8, [u8; size_of::<DsWifiClientMask>()], usize, usize) {
+        // AID 0 is never assigned, its bit is covered by the group bit in the bitmap control
+        let bitmap = (self.traffic_mask & !0x0001).to_le_bytes();
+        let first = bitmap.iter().position(|octet| *octet != 0).unwrap_or(0) & !1;
+        let last = bitmap.iter().rposition(|octet| *octet != 0).unwrap_or(first);
+        let bitmap_control = ((first / 2) as u8) << 1 | self.group_traffic as u8;
+        (bitmap_control, bitmap, first, last + 1)
+    }
+}
+
+impl MeasureWith<()> for TrafficIndicationMap {
+    fn measure_with(&self, _ctx: &()) -> usize {
+        let (_, _, start, end) = self.partial_virtual_bitmap();
+        3 + end - start
+    }
+}
+
+impl TryIntoCtx<()> for TrafficIndicationMap {
+    type Error = scroll::Error;
+
+    fn try_into_ctx(self, buf: &mut [u8], _ctx: ()) -> Result<usize, Self::Error> {
+        let mut offset = 0;
+        let (bitmap_control, bitmap, start, end) = self.partial_virtual_bitmap();
+
+        buf.gwrite_with(self.dtim_count, &mut offset, Little)?;
+        buf.gwrite_with(self.dtim_period, &mut offset, Little)?;
+        buf.gwrite_with(bitmap_control, &mut offset, Little)?;
+        buf.gwrite(&bitmap[start..end], &mut offset)?;
+
+        Ok(offset)
+    }
+}
+
+// The payload borrows from the buffer the frame was parsed from.
+pub struct ClientToHostDataFrame<'a> {
     pub payload_size: u16,
     pub flags: ClientToHostFlags,
-    pub payload: Option<([u8;300],u16)>,
+    pub payload: Option<&'a [u8]>,
     pub footer_seq_no: Option<u16>,
 }
 
@@ -114,11 +256,10 @@ bitflags! {
     }
 }
 
-impl TryFromCtx<'_, ()> for ClientToHostDataFrame<> {
-    type Error = scroll::Error;
+impl<'a> TryFromCtx<'a, ()> for ClientToHostDataFrame<'a> {
+    type Error = DsWiFiPacketError;
 
-    //TODO: do something better for the payload, this is dirty
-    fn try_from_ctx(from: &[u8], _: ()) -> Result<(Self, usize), Self::Error> {
+    fn try_from_ctx(from: &'a [u8], _: ()) -> Result<(Self, usize), Self::Error> {
         let mut offset = 0;
 
         let payload_size_raw: u8 = from.gread_with(&mut offset, Little)?;
@@ -128,23 +269,10 @@ impl TryFromCtx<'_, ()> for ClientToHostDataFrame<> {
         if !flags.contains(ClientToHostFlags::LENGTH_IS_BYTES) {
             payload_size = payload_size * 2; //length is halfwords by default unless this bit is set
         }
-        let payload = {
-            let mut payload = [0u8;300];
-            let mut local_payload_size = payload_size as usize;
-            if local_payload_size > 300 {
-                error!("ignoring payload size of {} bytes, max is 300", payload_size);
-                local_payload_size = 0;
-            }
-            if local_payload_size > 0 && local_payload_size < 300 {
-                payload[..local_payload_size].copy_from_slice(&from[offset..offset+local_payload_size]);
-            }
-            offset += local_payload_size;
-            if local_payload_size == 0 {
-                None
-            } else {
-                Some((payload,payload_size))
-            }
-        };
+        let payload = if payload_size > 0 {
+            let payload: &[u8] = read_payload(from, &mut offset, payload_size as usize, "payload_size")?;
+            Some(payload)
+        } else { None };
         let footer = if flags.contains(ClientToHostFlags::HAS_FOOTER) {
             let footer_raw: u16 = from.gread_with(&mut offset, Little)?;
             Some(footer_raw)
@@ -161,6 +289,68 @@ impl TryFromCtx<'_, ()> for ClientToHostDataFrame<> {
     }
 }
 
+impl ClientToHostDataFrame<'_> {
+    // halfword lengths can only describe even payloads, odd ones get a padding byte
+    fn padded_payload_size(&self) -> usize {
+        let size = self.payload.map_or(0, |payload| payload.len());
+        if self.flags.contains(ClientToHostFlags::LENGTH_IS_BYTES) {
+            size
+        } else {
+            size + (size & 1)
+        }
+    }
+}
+
+impl MeasureWith<()> for ClientToHostDataFrame<'_> {
+    fn measure_with(&self, _: &()) -> usize {
+        let mut frame_size = 0;
+
+        frame_size += 1; // payload_size
+        frame_size += 1; // flags
+        frame_size += self.padded_payload_size(); // payload
+        if self.footer_seq_no.is_some() {
+            frame_size += 2; // footer seq_number
+        }
+
+        frame_size
+    }
+}
+
+// The footer flag will be automatically set if a footer is provided, same as the host to client frame.
+impl TryIntoCtx<()> for ClientToHostDataFrame<'_> {
+    type Error = scroll::Error;
+
+    fn try_into_ctx(self, buf: &mut [u8], _: ()) -> Result<usize, Self::Error> {
+        let mut offset: usize = 0;
+        let payload = self.payload.unwrap_or(&[]);
+        let padded_payload_size = self.padded_payload_size();
+        let payload_size_raw = if self.flags.contains(ClientToHostFlags::LENGTH_IS_BYTES) {
+            padded_payload_size
+        } else {
+            padded_payload_size / 2
+        };
+        if payload_size_raw > u8::MAX as usize {
+            return Err(scroll::Error::BadInput { size: payload.len(), msg: "payload too large for length field" });
+        }
+        let mut flags = self.flags;
+        if self.footer_seq_no.is_some() {
+            flags.set(ClientToHostFlags::HAS_FOOTER, true);
+        }
+
+        buf.gwrite_with(payload_size_raw as u8, &mut offset, Endian::Little)?;
+        buf.gwrite_with(flags.bits(), &mut offset, Endian::Little)?;
+        buf.gwrite(payload, &mut offset)?;
+        if padded_payload_size > payload.len() {
+            buf.gwrite_with(0u8, &mut offset, Endian::Little)?;
+        }
+        if let Some(footer) = self.footer_seq_no {
+            buf.gwrite_with(footer, &mut offset, Endian::Little)?;
+        }
+
+        Ok(offset)
+    }
+}
+
 // The host to client data frame as I currently understand it.
 // The footer flag will be automatically set if a footer is provided.
 pub struct HostToClientDataFrame<Payload: TryIntoCtx<(), Error = scroll::Error> + MeasureWith<()>> {
@@ -239,7 +429,267 @@ impl<Payload: TryIntoCtx<Error = scroll::Error> + MeasureWith<()> + AsRef<[u8]>>
     }
 }
 
+impl<'a> TryFromCtx<'a, ()> for HostToClientDataFrame<&'a [u8]> {
+    type Error = DsWiFiPacketError;
+
+    fn try_from_ctx(from: &'a [u8], _: ()) -> Result<(Self, usize), Self::Error> {
+        let mut offset = 0;
+
+        let us_per_client_reply: u16 = from.gread_with(&mut offset, Little)?;
+        let client_target_mask: DsWifiClientMask = from.gread_with(&mut offset, Little)?;
+        let payload_size_raw: u8 = from.gread_with(&mut offset, Little)?;
+        let payload_size = payload_size_raw as usize * 2; //length is always halfwords from the host
+        let flags_raw: u8 = from.gread_with(&mut offset, Little)?;
+        let flags = HostToClientFlags::from_bits_truncate(flags_raw);
+        let payload = if payload_size > 0 {
+            let payload: &[u8] = read_payload(from, &mut offset, payload_size, "payload_size")?;
+            Some(payload)
+        } else { None };
+        let footer = if flags.contains(HostToClientFlags::HAS_FOOTER) {
+            Some(HostToClientFooter {
+                data_seq: from.gread_with(&mut offset, Little)?,
+                client_target_mask: from.gread_with(&mut offset, Little)?,
+            })
+        } else { None };
+
+        Ok((Self {
+            us_per_client_reply,
+            client_target_mask,
+            flags,
+            payload,
+            footer,
+        }, offset))
+    }
+}
+
 pub struct HostToClientFooter {
     pub data_seq: u16,
     pub client_target_mask: DsWifiClientMask,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An MP poll to AIDs 1 and 2 with a 4 halfword payload and the footer, the body following
+    // the 802.11 header.
+    const MP_POLL: [u8; 18] = [
+        0xf6, 0x01, // us_per_client_reply
+        0x06, 0x00, // client_target_mask
+        0x04, // payload size in halfwords
+        0x08, // flags, HAS_FOOTER
+        0x01, 0x00, 0x02, 0x00, 0x1c, 0x00, 0x00, 0x00, // payload
+        0x42, 0x00, // footer data_seq
+        0x06, 0x00, // footer client_target_mask
+    ];
+
+    #[test]
+    fn mp_poll_round_trip() {
+        let (poll, read) = HostToClientDataFrame::<&[u8]>::try_from_ctx(&MP_POLL, ()).unwrap();
+        assert_eq!(read, MP_POLL.len());
+        assert_eq!(poll.us_per_client_reply, 502);
+        assert_eq!(poll.client_target_mask, 0x0006);
+        assert_eq!(poll.flags, HostToClientFlags::HAS_FOOTER);
+        assert_eq!(poll.payload, Some(&MP_POLL[6..14]));
+        let footer = poll.footer.as_ref().unwrap();
+        assert_eq!(footer.data_seq, 0x42);
+        assert_eq!(footer.client_target_mask, 0x0006);
+
+        assert_eq!(poll.measure_with(&()), MP_POLL.len());
+        let mut buffer = [0u8; 32];
+        let written = buffer.pwrite(poll, 0).unwrap();
+        assert_eq!(&buffer[..written], &MP_POLL);
+    }
+
+    #[test]
+    fn mp_poll_without_payload_or_footer() {
+        let bytes = [0xf6, 0x01, 0x02, 0x00, 0x00, 0x00];
+        let (poll, read) = HostToClientDataFrame::<&[u8]>::try_from_ctx(&bytes, ()).unwrap();
+        assert_eq!(read, bytes.len());
+        assert!(poll.payload.is_none());
+        assert!(poll.footer.is_none());
+    }
+
+    #[test]
+    fn truncated_mp_poll_is_rejected() {
+        for len in 0..MP_POLL.len() {
+            assert!(HostToClientDataFrame::<&[u8]>::try_from_ctx(&MP_POLL[..len], ()).is_err(), "{} bytes were accepted", len);
+        }
+        assert_eq!(
+            HostToClientDataFrame::<&[u8]>::try_from_ctx(&MP_POLL[..3], ()).err(),
+            Some(DsWiFiPacketError::Truncated { needed: 2, available: 1 }),
+        );
+        // the payload size claims more than is there
+        assert_eq!(
+            HostToClientDataFrame::<&[u8]>::try_from_ctx(&MP_POLL[..10], ()).err(),
+            Some(DsWiFiPacketError::BadLength { field: "payload_size", value: 8 }),
+        );
+        assert_eq!(
+            HostToClientDataFrame::<&[u8]>::try_from_ctx(&MP_POLL[..15], ()).err(),
+            Some(DsWiFiPacketError::Truncated { needed: 2, available: 1 }),
+        );
+    }
+
+    fn client_reply_round_trip(reply: ClientToHostDataFrame) {
+        let (flags, payload, footer_seq_no) = (reply.flags, reply.payload, reply.footer_seq_no);
+        let measured = reply.measure_with(&());
+        let mut buffer = [0u8; 64];
+        let written = buffer.pwrite(reply, 0).unwrap();
+        assert_eq!(measured, written);
+
+        let (decoded, read) = ClientToHostDataFrame::try_from_ctx(&buffer[..written], ()).unwrap();
+        assert_eq!(read, written);
+        assert_eq!(decoded.footer_seq_no, footer_seq_no);
+        assert_eq!(decoded.flags.contains(ClientToHostFlags::HAS_FOOTER), footer_seq_no.is_some());
+        assert_eq!(decoded.flags & !ClientToHostFlags::HAS_FOOTER, flags & !ClientToHostFlags::HAS_FOOTER);
+        // odd payloads come back with their padding byte
+        let decoded_payload = decoded.payload.unwrap_or(&[]);
+        let payload = payload.unwrap_or(&[]);
+        assert_eq!(&decoded_payload[..payload.len()], payload);
+        assert!(decoded_payload[payload.len()..].iter().all(|byte| *byte == 0));
+        assert_eq!(decoded.payload_size as usize, decoded_payload.len());
+    }
+
+    #[test]
+    fn client_reply_with_halfword_length() {
+        client_reply_round_trip(ClientToHostDataFrame {
+            payload_size: 4,
+            flags: ClientToHostFlags::empty(),
+            payload: Some(&[0x01, 0x02, 0x03, 0x04]),
+            footer_seq_no: Some(0x0042),
+        });
+    }
+
+    #[test]
+    fn client_reply_with_odd_halfword_payload_is_padded() {
+        let reply = ClientToHostDataFrame {
+            payload_size: 3,
+            flags: ClientToHostFlags::empty(),
+            payload: Some(&[0x01, 0x02, 0x03]),
+            footer_seq_no: Some(0x0042),
+        };
+        let mut buffer = [0u8; 16];
+        let written = buffer.pwrite(reply, 0).unwrap();
+        assert_eq!(&buffer[..written], &[0x02, 0x08, 0x01, 0x02, 0x03, 0x00, 0x42, 0x00]);
+
+        client_reply_round_trip(ClientToHostDataFrame {
+            payload_size: 3,
+            flags: ClientToHostFlags::empty(),
+            payload: Some(&[0x01, 0x02, 0x03]),
+            footer_seq_no: None,
+        });
+    }
+
+    #[test]
+    fn client_reply_with_byte_length() {
+        client_reply_round_trip(ClientToHostDataFrame {
+            payload_size: 3,
+            flags: ClientToHostFlags::LENGTH_IS_BYTES,
+            payload: Some(&[0x01, 0x02, 0x03]),
+            footer_seq_no: Some(0x1234),
+        });
+    }
+
+    #[test]
+    fn empty_client_reply() {
+        client_reply_round_trip(ClientToHostDataFrame {
+            payload_size: 0,
+            flags: ClientToHostFlags::empty(),
+            payload: None,
+            footer_seq_no: Some(0x0001),
+        });
+    }
+
+    #[test]
+    fn oversized_client_reply_is_refused() {
+        let payload = [0u8; 256];
+        let reply = ClientToHostDataFrame {
+            payload_size: 256,
+            flags: ClientToHostFlags::LENGTH_IS_BYTES,
+            payload: Some(&payload),
+            footer_seq_no: None,
+        };
+        let mut buffer = [0u8; 300];
+        assert!(buffer.pwrite(reply, 0).is_err());
+    }
+
+    // The Nintendo vendor element of a PictoChat room beacon, starting with the element header.
+    const NINTENDO_ELEMENT: [u8; 34] = [
+        221, 32, // vendor specific element, 3 + 21 + 8 bytes
+        0x00, 0x09, 0xbf, // Nintendo OUI
+        0x00, // oui_type
+        0x0a, 0x00, // stepping_offset
+        0x00, 0x00, // lcd_video_sync
+        0x00, 0x00, 0x00, 0x0a, // fixed_id
+        0x00, 0x00, 0x00, 0x00, // game_id
+        0x0f, 0x0f, // stream_code
+        0x08, // payload size
+        0x01, // beacon_type, MULTICART
+        0xc0, 0x00, // cmd_data_size
+        0xc0, 0x00, // reply_data_size
+        0x48, 0x23, 0x11, 0x0a, 0x01, 0x00, 0x02, 0x00, // payload
+    ];
+
+    // The elements of a beacon body, the Nintendo one comes after another vendor's element.
+    fn beacon_elements(nintendo_element: &[u8]) -> Vec<u8> {
+        let mut elements = vec![
+            0, 0, // SSID
+            1, 2, 0x82, 0x84, // supported rates
+            3, 1, 7, // DS parameter set
+            221, 4, 0x00, 0x50, 0xf2, 0x01, // some other vendor
+        ];
+        elements.extend_from_slice(nintendo_element);
+        elements
+    }
+
+    #[test]
+    fn nintendo_element_is_found_and_parsed() {
+        let elements = beacon_elements(&NINTENDO_ELEMENT);
+        let tag = DSWiFiBeaconTag::find_in_elements(&elements).expect("the Nintendo element is found");
+        assert_eq!(tag.oui_type, 0);
+        assert_eq!(tag.stepping_offset, [0x0a, 0x00]);
+        assert_eq!(tag.fixed_id, [0x00, 0x00, 0x00, 0x0a]);
+        assert_eq!(tag.game_id, [0x00, 0x00, 0x00, 0x00]);
+        assert_eq!(tag.stream_code, 0x0f0f);
+        assert_eq!(tag.beacon_type, BeaconType::MULTICART);
+        assert_eq!(tag.cmd_data_size, 0xc0);
+        assert_eq!(tag.reply_data_size, 0xc0);
+        assert_eq!(tag.payload, Some(&NINTENDO_ELEMENT[26..]));
+
+        let (tag, read) = DSWiFiBeaconTag::<&[u8]>::try_from_ctx(&NINTENDO_ELEMENT[5..], ()).unwrap();
+        assert_eq!(read, NINTENDO_ELEMENT.len() - 5);
+        let mut buffer = [0u8; 64];
+        let written = buffer.pwrite(tag, 0).unwrap();
+        assert_eq!(&buffer[..written], &NINTENDO_ELEMENT[5..]);
+    }
+
+    #[test]
+    fn wrong_oui_is_ignored() {
+        let mut element = NINTENDO_ELEMENT;
+        element[4] = 0xbe;
+        assert!(DSWiFiBeaconTag::find_in_elements(&beacon_elements(&element)).is_none());
+    }
+
+    #[test]
+    fn short_nintendo_element_is_rejected() {
+        // the element ends in the middle of the game id
+        let mut element = NINTENDO_ELEMENT[..15].to_vec();
+        element[1] = 13;
+        assert!(DSWiFiBeaconTag::find_in_elements(&beacon_elements(&element)).is_none());
+        assert!(DSWiFiBeaconTag::<&[u8]>::try_from_ctx(&element[5..], ()).is_err());
+
+        // the element header claims more bytes than the beacon has
+        let elements = beacon_elements(&NINTENDO_ELEMENT);
+        assert!(DSWiFiBeaconTag::find_in_elements(&elements[..elements.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn payload_size_past_the_element_is_rejected() {
+        let mut element = NINTENDO_ELEMENT;
+        element[20] = 9;
+        assert_eq!(
+            DSWiFiBeaconTag::<&[u8]>::try_from_ctx(&element[5..], ()).err(),
+            Some(DsWiFiPacketError::BadLength { field: "payload_size", value: 9 }),
+        );
+        assert!(DSWiFiBeaconTag::find_in_elements(&beacon_elements(&element)).is_none());
+    }
+}
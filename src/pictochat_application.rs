@@ -1,18 +1,19 @@
 use alloc::vec;
 use alloc::vec::Vec;
 use core::slice::SlicePattern;
-use defmt::{error, info};
 use embassy_futures::join::join3;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::channel::Channel;
 use embassy_sync::mutex::Mutex;
 use embassy_time::{Duration, Ticker};
 use ieee80211::mac_parser::MACAddress;
+use ieee80211::scroll::ctx::MeasureWith;
 use ieee80211::scroll::{Endian, Pread, Pwrite};
-use crate::{DsWiFiClientEvent, DsWiFiControl, DsWiFiInterfaceControlEvent, DsWiFiInterfaceControlEventResponse, DsWifiClientMask, DsWifiClientMaskMath};
+use crate::{DsWiFiBeaconContents, DsWiFiClientEvent, DsWiFiControl, DsWifiClientMask, DsWifiClientMaskMath};
 use crate::DsWiFiControlEvent::FrameGenerated;
 use crate::packets::HostToClientFlags;
-use crate::pictochat_packets::{ConsoleIdPayload, PictochatHeader, PictochatType1, PictochatType2, PictochatType45};
+use crate::pictochat_packets::{ConsoleIdPayload, PictochatBeacon, PictochatChatroom, PictochatHeader, PictochatType1, PictochatType2, PictochatType45};
+use crate::radio::DsWiFiRadio;
 use crate::runner::PendingDataFrame;
 
 pub struct PictochatUser {
@@ -57,13 +58,13 @@ impl PictoChatUserManager {
         }
     }
 }
-pub struct PictoChatApplication<'res> {
-    pub ds_wifi_control: DsWiFiControl<'res>,
+pub struct PictoChatApplication<'res, R: DsWiFiRadio> {
+    pub ds_wifi_control: DsWiFiControl<'res, R>,
     pub user_state_manager: Mutex<NoopRawMutex, PictoChatUserManager>,
     pub state_queue: Channel<NoopRawMutex, PictoChatState, 20>
 }
 
-impl<'res> PictoChatApplication<'res> {
+impl<'res, R: DsWiFiRadio> PictoChatApplication<'res, R> {
     async fn generate_idle_frame(&self, frame: &mut PendingDataFrame, id: u16) {
         let mut idle = PictochatType45 {
             header: PictochatHeader {
@@ -86,6 +87,13 @@ impl<'res> PictoChatApplication<'res> {
         frame.size = written as u16;
     }
 
+    // Only fills up if the runner stops asking for frames, the state is dropped then.
+    fn queue_state(&self, state: PictoChatState) {
+        if self.state_queue.try_send(state).is_err() {
+            warn!("state queue full, dropping state");
+        }
+    }
+
     async fn get_state(&self) -> PictoChatState {
         let pending = self.state_queue.try_receive();
         if let Ok(state) = pending {
@@ -113,15 +121,16 @@ impl<'res> PictoChatApplication<'res> {
                 }
                 PictoChatState::TxTransfer => {}
                 PictoChatState::IdentConsole((mac)) => {
-                    if self.state_queue.free_capacity() > 4 {
-                        self.state_queue.try_send(PictoChatState::IdentConsoleInternalStage13).expect("Failed to send state");
-                        self.state_queue.try_send(PictoChatState::IdentConsoleInternalStage24((mac,[0x03,0x00]))).expect("Failed to send state");
-                        self.state_queue.try_send(PictoChatState::IdentConsoleInternalStage13).expect("Failed to send state");
-                        self.state_queue.try_send(PictoChatState::IdentConsoleInternalStage24((mac,[0x03,0x01]))).expect("Failed to send state");
-                        self.generate_idle_frame(&mut tx_out, 5).await;
+                    // the four stages only make sense together
+                    if self.state_queue.free_capacity() >= 4 {
+                        self.queue_state(PictoChatState::IdentConsoleInternalStage13);
+                        self.queue_state(PictoChatState::IdentConsoleInternalStage24((mac,[0x03,0x00])));
+                        self.queue_state(PictoChatState::IdentConsoleInternalStage13);
+                        self.queue_state(PictoChatState::IdentConsoleInternalStage24((mac,[0x03,0x01])));
                     } else {
-                        panic!("Not enough space in queue");
+                        warn!("state queue full, not identifying {:?}", mac);
                     }
+                    self.generate_idle_frame(&mut tx_out, 5).await;
                 }
                 PictoChatState::RequestIdent((id)) => {
                     tx_out.flags = HostToClientFlags::from_bits(29).unwrap();
@@ -174,9 +183,16 @@ impl<'res> PictoChatApplication<'res> {
     }
     async fn rx_wait_loop(&self) {
         loop {
-            let (data_raw,mac,size) = self.ds_wifi_control.data_rx.receive().await;
-            info!("Received data: {}", data_raw[0]);
-            let header: PictochatHeader = data_raw.pread(0).unwrap();
+            let data = self.ds_wifi_control.data_rx.receive().await;
+            let data_raw = data.payload();
+            let mac = data.source;
+            let header: PictochatHeader = match data_raw.pread(0) {
+                Ok(header) => header,
+                Err(e) => {
+                    error!("Dropping malformed pictochat frame from {:?}: {:?}", mac, e);
+                    continue;
+                }
+            };
             info!("Header: {:?}", header.type_id);
             if header.type_id == 6 {
                 let mut user_state_manager = self.user_state_manager.lock().await;
@@ -185,7 +201,7 @@ impl<'res> PictoChatApplication<'res> {
                     mask: 1,
                     id: 1,
                 });
-                self.state_queue.try_send(PictoChatState::NewClientPending).expect("Failed to send state");
+                self.queue_state(PictoChatState::NewClientPending);
                 /*
                 self.state_queue.try_send(PictoChatState::NewClientPending).expect("Failed to send state");
                 self.state_queue.try_send(PictoChatState::Idle).expect("Failed to send state");
@@ -197,10 +213,14 @@ impl<'res> PictoChatApplication<'res> {
                 self.state_queue.try_send(PictoChatState::IdentConsoleInternalStage24((MACAddress::from(self.ds_wifi_control.mac_address),[0x03,0x01]))).expect("Failed to send state");
                 */
             } else if header.type_id == 0 {
+                let Some(echo) = data_raw.get(..header.size_with_header as usize) else {
+                    error!("type 0 frame claims {} bytes but only {} were received", header.size_with_header, data_raw.len());
+                    continue;
+                };
                 let mut veccy_mc_vec_face = vec![0u8; header.size_with_header as usize];
-                veccy_mc_vec_face.copy_from_slice(data_raw[..header.size_with_header as usize].as_slice());
+                veccy_mc_vec_face.copy_from_slice(echo.as_slice());
                 veccy_mc_vec_face[0] = 1;
-                self.state_queue.try_send(PictoChatState::EchoTransfer(veccy_mc_vec_face)).unwrap()
+                self.queue_state(PictoChatState::EchoTransfer(veccy_mc_vec_face));
             }
         }
     }
@@ -212,6 +232,9 @@ impl<'res> PictoChatApplication<'res> {
                     info!("Client Connected: {:?}", mac);
 
                 },
+                DsWiFiClientEvent::FrameMissed(mac, data_seq) => {
+                    debug!("Client {:?} missed frame {}", mac, data_seq);
+                },
                 DsWiFiClientEvent::Disconnected(mac) => {
                     info!("Client Disconnected: {:?}", mac);
                     let mut user_state_manager = self.user_state_manager.lock().await;
@@ -221,21 +244,34 @@ impl<'res> PictoChatApplication<'res> {
         }
     }
     pub async fn run(&mut self) {
-        match self.ds_wifi_control.control_requester.send_request_and_wait(DsWiFiInterfaceControlEvent::SetChannel(7)).await {
-            DsWiFiInterfaceControlEventResponse::Success => {
-                info!("Set Channel to 7");
+        // the channel comes from the init info (or the start-up survey), switching here would
+        // override the survey's choice
+        let beacon = PictochatBeacon {
+            chatroom: PictochatChatroom::B,
+            ..Default::default()
+        };
+        let mut beacon_payload = vec![0u8; beacon.measure_with(&())];
+        beacon_payload.pwrite(beacon, 0).unwrap();
+        let beacon_contents = DsWiFiBeaconContents {
+            payload: beacon_payload,
+            client_count_offset: Some(PictochatBeacon::CLIENT_COUNT_OFFSET),
+            ..DsWiFiBeaconContents::new([0x00, 0x00, 0x00, 0x00])
+        };
+        match self.ds_wifi_control.set_beacon_contents(beacon_contents).await {
+            Ok(()) => {
+                info!("Set PictoChat beacon");
             },
-            DsWiFiInterfaceControlEventResponse::Failed => {
-                error!("Failed to set channel");
+            Err(e) => {
+                error!("Failed to set beacon contents: {:?}", e);
             }
         };
 
-        match self.ds_wifi_control.control_requester.send_request_and_wait(DsWiFiInterfaceControlEvent::SetBeaconsEnabled(true)).await {
-            DsWiFiInterfaceControlEventResponse::Success => {
+        match self.ds_wifi_control.set_beacons_enabled(true).await {
+            Ok(()) => {
                 info!("Set Beacons enabled");
             },
-            DsWiFiInterfaceControlEventResponse::Failed => {
-                error!("Failed to set beacons enabled");
+            Err(e) => {
+                error!("Failed to set beacons enabled: {:?}", e);
             }
         };
 
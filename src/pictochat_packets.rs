@@ -6,6 +6,8 @@ use ieee80211::scroll::ctx::{MeasureWith, TryFromCtx, TryIntoCtx};
 use ieee80211::scroll::{Endian, Pread, Pwrite};
 use ieee80211::scroll::Endian::Little;
 use crate::DsWifiClientMask;
+use crate::error::DsWiFiPacketError;
+use crate::packets::read_payload;
 
 pub struct PictochatBeacon {
     pub header: [u8; 4],
@@ -14,6 +16,11 @@ pub struct PictochatBeacon {
     pub footer: [u8; 2],
 }
 
+impl PictochatBeacon {
+    // where the encoded beacon keeps the number of consoles in the room
+    pub const CLIENT_COUNT_OFFSET: usize = 5;
+}
+
 impl Default for PictochatBeacon {
     fn default() -> Self {
         Self {
@@ -39,6 +46,25 @@ impl TryIntoCtx<()> for PictochatBeacon {
     }
 }
 
+impl TryFromCtx<'_, ()> for PictochatBeacon {
+    type Error = DsWiFiPacketError;
+
+    fn try_from_ctx(from: &[u8], _: ()) -> Result<(Self, usize), Self::Error> {
+        let mut offset = 0;
+        let header: [u8; 4] = from.gread_with(&mut offset, Little)?;
+        if header != PictochatBeacon::default().header {
+            return Err(DsWiFiPacketError::BadMagic { field: "header" });
+        }
+        let chatroom_raw: u8 = from.gread_with(&mut offset, Little)?;
+        Ok((Self {
+            header,
+            chatroom: PictochatChatroom::try_from(chatroom_raw)?,
+            client_count: from.gread_with(&mut offset, Little)?,
+            footer: from.gread_with(&mut offset, Little)?,
+        }, offset))
+    }
+}
+
 impl MeasureWith<()> for PictochatBeacon {
     fn measure_with(&self, _: &()) -> usize {
         let mut frame_size = 0;
@@ -52,7 +78,7 @@ impl MeasureWith<()> for PictochatBeacon {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum PictochatChatroom {
     A = 0x00,
@@ -61,6 +87,20 @@ pub enum PictochatChatroom {
     D = 0x03
 }
 
+impl TryFrom<u8> for PictochatChatroom {
+    type Error = DsWiFiPacketError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x00 => Ok(PictochatChatroom::A),
+            0x01 => Ok(PictochatChatroom::B),
+            0x02 => Ok(PictochatChatroom::C),
+            0x03 => Ok(PictochatChatroom::D),
+            _ => Err(DsWiFiPacketError::UnknownType { field: "chatroom", value: value as u16 }),
+        }
+    }
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct PictochatHeader {
     pub type_id: u16,
@@ -84,7 +124,7 @@ impl TryIntoCtx<()> for PictochatHeader {
 }
 
 impl TryFromCtx<'_, ()> for PictochatHeader {
-    type Error = scroll::Error;
+    type Error = DsWiFiPacketError;
 
     fn try_from_ctx(from: &[u8], _: ()) -> Result<(Self, usize), Self::Error> {
         let mut offset = 0;
@@ -196,12 +236,16 @@ impl TryIntoCtx<()> for PictochatType1 {
 }
 
 impl TryFromCtx<'_, ()> for PictochatType1 {
-    type Error = scroll::Error;
+    type Error = DsWiFiPacketError;
 
     fn try_from_ctx(from: &[u8], ctx: ()) -> Result<(Self, usize), Self::Error> {
         let mut offset = 0;
+        let header: PictochatHeader = from.gread_with(&mut offset, ctx)?;
+        if header.type_id != 1 {
+            return Err(DsWiFiPacketError::UnknownType { field: "type_id", value: header.type_id });
+        }
         Ok((Self {
-            header: from.gread_with(&mut offset, ctx)?,
+            header,
             console_id: from.gread_with(&mut offset, Little)?,
             magic_1: from.gread_with(&mut offset, Little)?,
             data_size: from.gread_with(&mut offset, Little)?,
@@ -254,19 +298,20 @@ impl TryIntoCtx<()> for PictochatType2 {
 }
 
 impl TryFromCtx<'_, ()> for PictochatType2 {
-    type Error = scroll::Error;
+    type Error = DsWiFiPacketError;
 
     fn try_from_ctx(from: &[u8], ctx: ()) -> Result<(Self, usize), Self::Error> {
         let mut offset = 0;
         let header: PictochatHeader = from.gread_with(&mut offset, ctx)?;
+        if header.type_id != 2 {
+            return Err(DsWiFiPacketError::UnknownType { field: "type_id", value: header.type_id });
+        }
         let sending_console_id: u8 = from.gread_with(&mut offset, Little)?;
         let payload_type: u8 = from.gread_with(&mut offset, Little)?;
         let payload_length: u8 = from.gread_with(&mut offset, Little)?;
         let transfer_flags: u8 = from.gread_with(&mut offset, Little)?;
         let write_offset: u16 = from.gread_with(&mut offset, Little)?;
-        let mut payload = vec![0u8; payload_length as usize];
-        payload.copy_from_slice(&from[offset..offset+payload_length as usize]);
-        offset += payload_length as usize;
+        let payload = read_payload(from, &mut offset, payload_length as usize, "payload_length")?.to_vec();
         Ok((Self {
             header,
             sending_console_id,
@@ -339,7 +384,7 @@ impl TryIntoCtx<()> for ConsoleIdPayload {
 }
 
 impl TryFromCtx<'_, ()> for ConsoleIdPayload {
-    type Error = scroll::Error;
+    type Error = DsWiFiPacketError;
 
     fn try_from_ctx(from: &[u8], ctx: ()) -> Result<(Self, usize), Self::Error> {
         let mut offset = 0;
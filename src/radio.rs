@@ -0,0 +1,72 @@
+use core::ops::DerefMut;
+
+// The radio backend the runner talks to. The FoA implementation drives the real ESP32 MAC,
+// the in-memory one lets the whole runner run on the host.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WiFiRate {
+    PhyRate1ML,
+    PhyRate2ML,
+    PhyRate5ML,
+    PhyRate11ML,
+    PhyRate2MS,
+    PhyRate5MS,
+    PhyRate11MS,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TxErrorBehaviour {
+    RetryUntil(usize),
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TxParameters {
+    pub rate: WiFiRate,
+    pub duration: u16,
+    pub override_seq_num: bool,
+    pub tx_error_behaviour: TxErrorBehaviour,
+    pub tx_timeout: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RxFilterBank {
+    BSSID,
+    ReceiverAddress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DsWiFiRadioError {
+    ChannelLockFailed,
+    TxFailed,
+}
+
+pub trait DsWiFiRxBuffer {
+    fn mpdu_buffer(&self) -> &[u8];
+    fn rssi(&self) -> i8;
+}
+
+#[allow(async_fn_in_trait)]
+pub trait DsWiFiRadio {
+    type RxBuffer: DsWiFiRxBuffer;
+
+    fn mac_address(&self) -> [u8; 6];
+
+    async fn alloc_tx_buf(&self) -> impl DerefMut<Target = [u8]> + '_;
+    async fn transmit(&self, buffer: &mut [u8], tx_parameters: &TxParameters, wait_for_ack: bool) -> Result<(), DsWiFiRadioError>;
+    async fn receive(&self) -> Self::RxBuffer;
+
+    fn lock_channel(&self, channel: u8) -> Result<(), DsWiFiRadioError>;
+    fn set_filter_parameters(&self, bank: RxFilterBank, mac_address: [u8; 6], mask: Option<[u8; 6]>);
+    fn set_filter_status(&self, bank: RxFilterBank, enabled: bool);
+    fn set_tx_power(&self, max_tx_power: i8);
+
+    // DS WiFi never gives up its channel, so the backend rejects any off channel request once this
+    // resolves. Backends without other interfaces can just never resolve.
+    async fn reject_off_channel_request(&self);
+}
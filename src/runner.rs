@@ -2,8 +2,7 @@ use core::cmp::PartialEq;
 use core::future::{join, Future};
 use core::intrinsics::{black_box, unreachable};
 use core::marker::PhantomData;
-use core::sync::atomic::{AtomicU16, Ordering};
-use defmt::{debug, error, info, trace, warn};
+use core::sync::atomic::{AtomicBool, AtomicI8, AtomicU16, AtomicU8, AtomicUsize, Ordering};
 use embassy_futures::join::join;
 use embassy_futures::select::{select, select3, select4, Either, Either3, Either4};
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
@@ -11,37 +10,39 @@ use embassy_sync::channel::{DynamicReceiver, DynamicSender};
 use embassy_sync::mutex::Mutex;
 use embassy_sync::signal::Signal;
 use embassy_time::{Duration, Instant, Ticker, Timer};
-use foa::esp_wifi_hal::{BorrowedBuffer, TxErrorBehaviour, TxParameters, WiFiRate};
-use foa::lmac::{LMacError, LMacInterfaceControl, OffChannelRequest};
-use foa::RxQueueReceiver;
 use hex_literal::hex;
-use ieee80211::common::{AssociationID, CapabilitiesInformation, DataFrameCF, DataFrameSubtype, FCFFlags, FrameType, IEEE80211AuthenticationAlgorithmNumber, IEEE80211StatusCode, SequenceControl};
+use ieee80211::common::{CapabilitiesInformation, DataFrameCF, DataFrameSubtype, FCFFlags, FrameType, IEEE80211AuthenticationAlgorithmNumber, IEEE80211Reason, IEEE80211StatusCode, SequenceControl};
 use ieee80211::{element_chain, match_frames, supported_rates, GenericFrame};
 use ieee80211::data_frame::builder::DataFrameBuilder;
-use ieee80211::data_frame::{DataFrame, DataFrameReadPayload};
+use ieee80211::data_frame::DataFrame;
 use ieee80211::data_frame::header::DataFrameHeader;
 use ieee80211::elements::{DSSSParameterSetElement, RawIEEE80211Element, VendorSpecificElement};
 use ieee80211::elements::rates::SupportedRatesElement;
 use ieee80211::mac_parser::{MACAddress, BROADCAST};
-use ieee80211::mgmt_frame::{AssociationRequestFrame, AssociationResponseFrame, AuthenticationFrame, BeaconFrame, DeauthenticationFrame, ManagementFrameHeader};
-use ieee80211::mgmt_frame::body::{AssociationResponseBody, AuthenticationBody, BeaconBody};
+use ieee80211::mgmt_frame::{AssociationRequestFrame, AssociationResponseFrame, AuthenticationFrame, BeaconFrame, DeauthenticationFrame, DisassociationFrame, ManagementFrameHeader};
+use ieee80211::mgmt_frame::body::{AssociationResponseBody, AuthenticationBody, BeaconBody, DeauthenticationBody};
 use ieee80211::scroll::ctx::TryFromCtx;
 use ieee80211::scroll::Pwrite;
-use crate::{DsWiFiClient, DsWiFiClientEvent, DsWiFiClientManager, DsWiFiClientState, DsWiFiControlEvent, DsWiFiInterfaceControlEvent, DsWiFiInterfaceControlEventResponse, DsWiFiSharedResources, DsWifiAidClientMaskBits, DsWifiClientMaskMath, Responder, MAX_CLIENTS};
+use crate::{DsWiFiClient, DsWiFiClientEvent, DsWiFiReceivedData, DsWiFiClientManager, DsWiFiBeaconContents, DsWiFiClientState, DsWiFiControlEvent, DsWiFiInterfaceControlEvent, DsWiFiInterfaceControlEventResponse, DsWiFiInterfaceStats, DsWiFiSharedResources, DsWifiAidClientMaskBits, DsWifiClientMask, DsWifiClientMaskMath, DsWiFiRetryLimits, Responder, MAX_CLIENTS};
 use crate::DsWiFiControlEvent::FrameRequired;
-use crate::DsWiFiInterfaceControlEventResponse::{Failed, Success};
-use crate::packets::{BeaconType, ClientToHostDataFrame, DSWiFiBeaconTag, HostToClientDataFrame, HostToClientFlags, HostToClientFooter};
-use crate::pictochat_packets::{PictochatBeacon, PictochatChatroom};
+use crate::DsWiFiInterfaceControlEventResponse::{Clients, Failed, ScanResults, Stats, Success};
+use crate::airtime::{acked_frame_duration, air_time_micros, clamp_duration, FCS_SIZE, MAX_DURATION_MICROS, SIFS_MICROS};
+use crate::error::DsWiFiControlError;
+use crate::packets::{ClientToHostDataFrame, DSWiFiBeaconTag, HostToClientDataFrame, HostToClientFlags, HostToClientFooter, TrafficIndicationMap, NINTENDO_OUI};
+use crate::radio::{DsWiFiRadio, DsWiFiRxBuffer, RxFilterBank, TxErrorBehaviour, TxParameters, WiFiRate};
+use crate::scanner::{DsChannelSurvey, DsHostInfo, DsHostScan, BEACON_ELEMENTS_OFFSET, DS_CHANNELS};
 
 pub struct PendingDataFrame {
     pub data: [u8; 300],
     pub size: u16,
     pub flags: HostToClientFlags,
+    // how often this frame was resent under its data_seq, reset when the next frame is requested
+    pub(crate) retries: u8,
 }
-pub struct DsWiFiRunner<'vif,'foa> {
-    pub(crate) interface_control: &'vif LMacInterfaceControl<'foa>,
+pub struct DsWiFiRunner<'vif, R: DsWiFiRadio> {
+    pub(crate) radio: &'vif R,
     pub(crate) mac_address: [u8; 6],
-    pub(crate) bg_rx_queue: DynamicReceiver<'vif, BorrowedBuffer<'foa>>,
+    pub(crate) bg_rx_queue: DynamicReceiver<'vif, R::RxBuffer>,
     pub(crate) client_manager: &'vif Mutex<NoopRawMutex, DsWiFiClientManager>,
     pub(crate) ack_rx_queue: DynamicReceiver<'vif, (MACAddress, Instant)>,
     pub(crate) start_time: Instant,
@@ -50,124 +51,162 @@ pub struct DsWiFiRunner<'vif,'foa> {
     pub(crate) data_tx_signal_2: &'vif Signal<NoopRawMutex, DsWiFiControlEvent>,
     pub(crate) control_responder: Responder<'vif, DsWiFiInterfaceControlEvent, DsWiFiInterfaceControlEventResponse>,
     pub(crate) beacons_enabled: Mutex<NoopRawMutex, bool>,
+    pub(crate) beacon_interval: Mutex<NoopRawMutex, Duration>,
+    pub(crate) mp_poll_interval: Mutex<NoopRawMutex, Duration>,
+    pub(crate) accepting_clients: AtomicBool,
+    pub(crate) tx_power: AtomicI8,
+    pub(crate) beacon_contents: Mutex<NoopRawMutex, DsWiFiBeaconContents>,
+    pub(crate) stream_code: u16,
+    pub(crate) dtim_period: u8,
+    pub(crate) dtim_count: AtomicU8,
+    pub(crate) mgmt_seq_num: AtomicU16,
+    pub(crate) data_seq_num: AtomicU16,
+    pub(crate) channel: AtomicU8,
+    pub(crate) auto_channel_dwell_time: Option<Duration>,
+    pub(crate) scan: Mutex<NoopRawMutex, Option<DsHostScan>>,
     pub(crate) event_tx: DynamicSender<'vif,DsWiFiClientEvent>,
     pub(crate) data_seq: AtomicU16,
-    pub(crate) interface_rx_queue: &'vif mut RxQueueReceiver<'foa>,
-    pub(crate) bg_rx_queue_sender: DynamicSender<'vif, BorrowedBuffer<'foa>>,
+    pub(crate) mp_poll_count: AtomicU8,
+    pub(crate) retry_limits: Mutex<NoopRawMutex, DsWiFiRetryLimits>,
+    pub(crate) bg_rx_queue_sender: DynamicSender<'vif, R::RxBuffer>,
     pub(crate) ack_rx_queue_sender: DynamicSender<'vif, (MACAddress, Instant)>,
-    pub(crate) data_rx_queue_sender: DynamicSender<'vif,([u8;300], MACAddress, u16)>,
+    pub(crate) data_rx_queue_sender: DynamicSender<'vif, DsWiFiReceivedData<R::RxBuffer>>,
+    pub(crate) dropped_frames: &'vif AtomicUsize,
 }
 
-/* ChatGPT wrote these 2 functions, it may be wrong */
-/// Calculates the air duration (in microseconds) of a Wi-Fi frame.
-/// `frame_size` is in bytes (total payload size, including MAC overhead).
-pub fn calculate_air_duration(rate: WiFiRate, frame_size: usize) -> u16 {
-    match rate {
-        WiFiRate::PhyRate1ML => {
-            // 1 Mbps long preamble timings:
-            // Long preamble = 192 microseconds, Header = 48 bits (48μs at 1 Mbps)
-            let preamble = 192; // 192 μs
-            let header = 48; // 48 μs
-            let payload_time = calculate_payload_time(1, frame_size); // rate = 1 Mbps
-            preamble + header + payload_time
-        }
-        WiFiRate::PhyRate2ML => {
-            // 2 Mbps long preamble timings:
-            // Long preamble = 192 microseconds, Header = 24 bits (12μs at 2 Mbps)
-            let preamble = 192; // 192 μs
-            let header = 24; // 24 μs
-            let payload_time = calculate_payload_time(2, frame_size); // rate = 2 Mbps
-            preamble + header + payload_time
-        }
-        WiFiRate::PhyRate2MS => {
-            // 2 Mbps short preamble timings:
-            // Short preamble = 96 microseconds, Header = 24 bits (12μs at 2 Mbps)
-            let preamble = 96; // 96 μs
-            let header = 24; // 24 μs
-            let payload_time = calculate_payload_time(2, frame_size); // rate = 2 Mbps
-            preamble + header + payload_time
-        }
-        _ => {
-            todo!("unsupported rate")
-        }
+pub(crate) fn is_valid_channel(channel: u8) -> bool {
+    (1..=14).contains(&channel)
+}
+
+// Beacons advertise their interval in TUs of 1024µs, None if it doesn't fit.
+pub(crate) fn beacon_interval_tu(interval: Duration) -> Option<u16> {
+    let tu = interval.as_micros() / 1024;
+    if tu == 0 || tu > u16::MAX as u64 {
+        return None;
     }
+    Some(tu as u16)
+}
+
+// MP polls, the client replies and our ack all go out at this rate
+const MP_RATE: WiFiRate = WiFiRate::PhyRate2MS;
+// header, payload and FCS of the ack that ends an MP exchange
+const MP_ACK_FRAME_SIZE: usize = 24 + 4 + FCS_SIZE;
+
+// header, the reply header and footer and the FCS of an MP reply, on top of its payload
+const MP_REPLY_OVERHEAD: usize = 24 + 2 + 2 + FCS_SIZE;
+// slack on top of the reply window for getting the last reply through the rx path
+const MP_REPLY_GUARD_MICROS: u32 = 1000;
+// errored clients are only included in every this many polls, in case they come back
+const ERRORED_POLL_INTERVAL: u8 = 8;
+
+// Time one client gets to answer an MP poll, SIFS and a reply carrying `reply_data_size` bytes.
+// Clients reply back to back in AID order, each one in its own slot.
+fn mp_reply_slot_micros(rate: WiFiRate, reply_data_size: u16) -> u32 {
+    SIFS_MICROS + air_time_micros(rate, MP_REPLY_OVERHEAD + reply_data_size as usize)
+}
+
+// Duration/ID of an MP poll, which reserves the medium for the client replies and our ack.
+fn mp_poll_duration_micros(rate: WiFiRate, reply_window_micros: u32) -> u32 {
+    reply_window_micros + SIFS_MICROS + air_time_micros(rate, MP_ACK_FRAME_SIZE)
 }
 
-fn calculate_payload_time(rate_mbps: u16, frame_size: usize) -> u16 {
-    // Convert frame size from bytes to bits (1 byte = 8 bits)
-    let frame_bits = frame_size as u16 * 8;
-    // Divide frame bits by rate (Mbps) to get time in microseconds
-    (frame_bits + (rate_mbps - 1)) / rate_mbps // Ceiling division
+// Whether a poll to every client can still reserve the medium for all replies of this size, which
+// also keeps the reply slot within the poll's 16 bit us_per_client_reply.
+pub(crate) fn mp_reply_size_fits(reply_data_size: u16) -> bool {
+    let reply_window_micros = mp_reply_slot_micros(MP_RATE, reply_data_size) * MAX_CLIENTS as u32;
+    mp_poll_duration_micros(MP_RATE, reply_window_micros) <= MAX_DURATION_MICROS
 }
-/* I hope it's not though */
 
-fn ds_tx_params_for_dataframe(rate: WiFiRate, frame_size: usize, tx_error_behaviour: TxErrorBehaviour) -> TxParameters {
-    let air_duration = calculate_air_duration(rate, frame_size);
+fn ds_tx_params_for_dataframe(rate: WiFiRate, duration: u16, tx_error_behaviour: TxErrorBehaviour) -> TxParameters {
     TxParameters {
         rate,
-        duration: air_duration,
-        override_seq_num: true,
+        duration,
+        override_seq_num: false,
         tx_error_behaviour,
         tx_timeout: 0,
     }
 }
-impl<'foa> DsWiFiRunner<'_,'foa> {
+impl<R: DsWiFiRadio> DsWiFiRunner<'_, R> {
+    // 802.11 sequence numbers, management and data frames count separately. These are not the
+    // data_seq in the MP footer.
+    fn next_mgmt_sequence_control(&self) -> SequenceControl {
+        SequenceControl::new().with_sequence_number(self.mgmt_seq_num.fetch_add(1, Ordering::Relaxed) & 0x0fff)
+    }
+
+    fn next_data_sequence_control(&self) -> SequenceControl {
+        SequenceControl::new().with_sequence_number(self.data_seq_num.fetch_add(1, Ordering::Relaxed) & 0x0fff)
+    }
+
     async fn handle_auth_frame(&self, auth: AuthenticationFrame<'_>) {
         if auth.body.authentication_algorithm_number != IEEE80211AuthenticationAlgorithmNumber::OpenSystem {
             info!("Got Auth Frame but it was not OpenSystem");
             return;
         }
-
-
-        let mut client_manager = self.client_manager.lock().await;
-
-        let client_already_exists = client_manager.has_client(auth.header.transmitter_address);
-        if client_already_exists {
-            todo!("client already exists, need to drop old clients");
+        // we aren't hosting while a scan runs, a client joining now would just time out
+        if self.is_scanning().await {
+            return;
         }
 
-        let next_aid = client_manager.get_next_client_aid();
-
-        if next_aid.is_none() {
-            panic!("All client slots filled, can't associate new client");
-        }
+        // events are only sent once the client manager is unlocked, the application may be
+        // waiting on a control request instead of reading them
+        let (status_code, stale_session) = {
+            let mut client_manager = self.client_manager.lock().await;
 
-        client_manager.add_client(DsWiFiClient {
-            state: DsWiFiClientState::Associating,
-            associated_mac_address: *auth.header.transmitter_address,
-            association_id: next_aid.unwrap(),
-            last_heard_from: Instant::now(),
-        });
+            // a known console authenticating again has lost its session (e.g. after a power dip),
+            // drop the stale one and let it rejoin under the same aid
+            let stale_aid = client_manager.get_client(auth.header.transmitter_address).map(|client| client.association_id);
+            if let Some(aid) = stale_aid {
+                info!("client {:?} re-authenticated, dropping stale session with aid {}", *auth.header.transmitter_address, aid.aid());
+                client_manager.remove_client(aid);
+            }
 
+            let status_code = if stale_aid.is_none() && !self.accepting_clients.load(Ordering::Relaxed) {
+                info!("not accepting new clients, refusing {:?}", *auth.header.transmitter_address);
+                IEEE80211StatusCode::UnspecifiedFailure
+            } else if let Some(aid) = stale_aid.or_else(|| client_manager.get_next_client_aid()) {
+                client_manager.add_client(DsWiFiClient::new(*auth.header.transmitter_address, aid));
+                IEEE80211StatusCode::Success
+            } else {
+                warn!("all client slots filled, refusing {:?}", *auth.header.transmitter_address);
+                IEEE80211StatusCode::DeniedNoMoreSTAs
+            };
+            (status_code, stale_aid.is_some())
+        };
+        if stale_session {
+            self.event_tx.send(DsWiFiClientEvent::Disconnected(*auth.header.transmitter_address)).await;
+        }
 
-        let mut buffer = self.interface_control.alloc_tx_buf().await;
+        let mut buffer = self.radio.alloc_tx_buf().await;
 
         let frame = AuthenticationFrame {
             header: ManagementFrameHeader {
                 receiver_address: auth.header.transmitter_address,
                 transmitter_address: MACAddress::from(self.mac_address),
                 bssid: MACAddress::from(self.mac_address),
-                sequence_control: SequenceControl::new(),
+                sequence_control: self.next_mgmt_sequence_control(),
                 ..Default::default()
             },
             body: AuthenticationBody {
                 authentication_algorithm_number: IEEE80211AuthenticationAlgorithmNumber::OpenSystem,
                 authentication_transaction_sequence_number: 2,
-                status_code: IEEE80211StatusCode::Success,
+                status_code,
                 elements: element_chain!(),
                 _phantom: Default::default()
             },
         };
 
-        let written = buffer.pwrite_with(frame, 0, false).unwrap();
+        let Ok(written) = buffer.pwrite_with(frame, 0, false) else {
+            error!("failed to write the authentication response");
+            return;
+        };
 
-        let _ = self.interface_control.transmit(
+        let _ = self.radio.transmit(
             &mut buffer[..written],
             &TxParameters {
                 rate: WiFiRate::PhyRate2MS,
-                duration: 248,
+                duration: acked_frame_duration(WiFiRate::PhyRate2MS),
                 tx_error_behaviour: TxErrorBehaviour::RetryUntil(4),
-                override_seq_num: true,
+                override_seq_num: false,
                 tx_timeout: 10,
             },
             true
@@ -180,14 +219,27 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
         info!("assoc request");
         let mut client_manager = self.client_manager.lock().await;
 
-        let client = client_manager.get_client(assoc.header.transmitter_address).unwrap();
+        // consoles that skipped authentication (or got dropped in between) are refused, a repeated
+        // request means our response got lost and is answered again
+        let client_state = client_manager.get_client(assoc.header.transmitter_address).map(|client| (client.association_id, client.state));
+        let association_id = match client_state {
+            Some((aid, DsWiFiClientState::Authenticated | DsWiFiClientState::Associated)) => Some(aid),
+            Some((_, state)) => {
+                warn!("assoc request from {:?} in state {:?}", *assoc.header.transmitter_address, state);
+                None
+            }
+            None => {
+                warn!("assoc request from unknown client {:?}", *assoc.header.transmitter_address);
+                None
+            }
+        };
 
         let mut caps = CapabilitiesInformation::new();
         caps.set_is_ess(true);
         //
         caps.set_is_short_preamble_allowed(true);
 
-        let mut buffer = self.interface_control.alloc_tx_buf().await;
+        let mut buffer = self.radio.alloc_tx_buf().await;
 
         let frame = AssociationResponseFrame {
             header: ManagementFrameHeader {
@@ -195,14 +247,14 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
                 receiver_address: assoc.header.transmitter_address,
                 transmitter_address: MACAddress::from(self.mac_address),
                 bssid: MACAddress::from(self.mac_address),
-                sequence_control: SequenceControl::new(),
-                duration: 162,
+                sequence_control: self.next_mgmt_sequence_control(),
+                duration: acked_frame_duration(WiFiRate::PhyRate2MS),
                 ht_control: None,
             },
             body: AssociationResponseBody {
                 capabilities_info: caps,
-                status_code: IEEE80211StatusCode::Success,
-                association_id: Option::from(client.association_id),
+                status_code: if association_id.is_some() { IEEE80211StatusCode::Success } else { IEEE80211StatusCode::UnspecifiedFailure },
+                association_id,
                 elements: element_chain! {
                         supported_rates![
                             1 B,
@@ -213,91 +265,208 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
             }
         };
 
-        let written = buffer.pwrite_with(frame, 0, false).unwrap();
+        let Ok(written) = buffer.pwrite_with(frame, 0, false) else {
+            error!("failed to write the association response");
+            return;
+        };
 
-        let _ = self.interface_control.transmit(
+        let _ = self.radio.transmit(
             &mut buffer[..written],
             &TxParameters {
                 rate: WiFiRate::PhyRate2MS,
-                duration: 248,
+                duration: acked_frame_duration(WiFiRate::PhyRate2MS),
                 tx_error_behaviour: TxErrorBehaviour::RetryUntil(4),
-                override_seq_num: true,
+                override_seq_num: false,
                 tx_timeout: 10,
             },
             true
         ).await;
 
+        if !matches!(client_state, Some((_, DsWiFiClientState::Authenticated))) {
+            return;
+        }
 
-        client_manager.update_client_state(assoc.header.transmitter_address,DsWiFiClientState::Connected);
-        self.event_tx.send(DsWiFiClientEvent::Connected(*assoc.header.transmitter_address)).await;
+        // the client only counts as connected once it answers its first poll
+        if let Err(e) = client_manager.update_client_state(assoc.header.transmitter_address, DsWiFiClientState::Associated) {
+            warn!("failed to mark client as associated: {:?}", e);
+        }
 
         Timer::after_micros(500).await;
     }
 
-    async fn handle_deauth(&self, deauth: DeauthenticationFrame<'_>) {
-        let mut client_manager = self.client_manager.lock().await;
+    // Forgets about a client and tells the application, returns false if we didn't know it.
+    async fn remove_client_by_mac(&self, mac: MACAddress) -> bool {
+        {
+            let mut client_manager = self.client_manager.lock().await;
 
-        let aid = client_manager.get_client(deauth.header.transmitter_address).unwrap().association_id;
-        self.event_tx.send(DsWiFiClientEvent::Disconnected(*deauth.header.transmitter_address)).await;
+            let Some(aid) = client_manager.get_client(mac).map(|client| client.association_id) else {
+                return false;
+            };
+            client_manager.remove_client(aid);
+            info!("disconnected client with aid {}", aid.aid());
+        }
+        self.event_tx.send(DsWiFiClientEvent::Disconnected(*mac)).await;
+        true
+    }
 
-        info!("disconnecting client with aid {} due to deauth frame", aid.aid());
+    async fn handle_deauth(&self, deauth: DeauthenticationFrame<'_>) {
+        info!("deauth from {:?}, reason {:?}", *deauth.header.transmitter_address, deauth.body.reason);
+        if !self.remove_client_by_mac(deauth.header.transmitter_address).await {
+            info!("ignoring deauth from unknown client {:?}", *deauth.header.transmitter_address);
+        }
+    }
 
-        client_manager.remove_client(aid);
+    async fn handle_disassoc(&self, disassoc: DisassociationFrame<'_>) {
+        info!("disassoc from {:?}, reason {:?}", *disassoc.header.transmitter_address, disassoc.body.reason);
+        if !self.remove_client_by_mac(disassoc.header.transmitter_address).await {
+            info!("ignoring disassoc from unknown client {:?}", *disassoc.header.transmitter_address);
+        }
     }
-    async fn update_client_rx_time(&self, mac: MACAddress) {
+    async fn record_client_rx(&self, mac: MACAddress, rssi: i8, payload_size: usize) {
         let mut client_manager = self.client_manager.lock().await;
         if let Some(client) = client_manager.get_client_mut(mac) {
             client.last_heard_from = Instant::now();
+            client.stats.record_rssi(rssi);
+            client.stats.bytes_in += payload_size as u64;
         };
     }
     async fn handle_bg_rx(
         &self,
-        buffer: BorrowedBuffer<'_>,
+        buffer: R::RxBuffer,
     ) {
         let _ = match_frames! {
             buffer.mpdu_buffer(),
             deauth = DeauthenticationFrame => {
                 self.handle_deauth(deauth).await;
             }
+            disassoc = DisassociationFrame => {
+                self.handle_disassoc(disassoc).await;
+            }
             auth = AuthenticationFrame => {
                 self.handle_auth_frame(auth).await;
             }
             assoc = AssociationRequestFrame => {
                 self.handle_assoc_req_frame(assoc).await;
             }
+            beacon = BeaconFrame => {
+                self.handle_scan_beacon(*beacon.header.bssid, buffer.rssi(), buffer.mpdu_buffer()).await;
+            }
+        };
+    }
+
+    async fn handle_scan_beacon(&self, bssid: [u8; 6], rssi: i8, mpdu: &[u8]) {
+        let mut scan = self.scan.lock().await;
+        let Some(scan) = scan.as_mut() else {
+            return;
+        };
+        if bssid == self.mac_address {
+            return;
+        }
+        let Some(elements) = mpdu.get(BEACON_ELEMENTS_OFFSET..) else {
+            return;
         };
+        if let Some(host) = DsHostInfo::from_beacon_elements(bssid, scan.current_channel(), rssi, elements) {
+            debug!("scan found host {:?} on channel {}", host.bssid, host.channel);
+            scan.record(host);
+        }
+    }
+
+    async fn start_scan(&self, dwell_time: Duration) -> Result<(), DsWiFiControlError> {
+        let mut scan = self.scan.lock().await;
+        if scan.is_some() {
+            return Err(DsWiFiControlError::Scanning);
+        }
+        if self.client_manager.lock().await.clients.iter().any(Option::is_some) {
+            return Err(DsWiFiControlError::ClientsConnected);
+        }
+        let mut new_scan = DsHostScan::new(dwell_time, self.channel.load(Ordering::Relaxed));
+        new_scan.request_id = Some(self.control_responder.current_request_id());
+        self.radio.lock_channel(new_scan.current_channel())?;
+        // other hosts use their own bssid, so let everything through while scanning
+        self.radio.set_filter_status(RxFilterBank::BSSID, false);
+        *scan = Some(new_scan);
+        Ok(())
+    }
+
+    // Hops to the next channel once the dwell time is up and answers the scan request after the last one.
+    async fn scan_tick(&self) {
+        let deadline = self.scan.lock().await.as_ref().map(|scan| scan.channel_deadline());
+        let Some(deadline) = deadline else {
+            // nothing to do until a scan is started, tick gets restarted when that happens
+            return core::future::pending().await;
+        };
+        Timer::at(deadline).await;
+
+        let mut scan = self.scan.lock().await;
+        let Some(current_scan) = scan.as_mut() else {
+            return;
+        };
+        if current_scan.next_channel() {
+            if self.radio.lock_channel(current_scan.current_channel()).is_err() {
+                warn!("failed to switch to channel {} for scanning", current_scan.current_channel());
+            }
+            return;
+        }
+
+        let finished = scan.take().unwrap();
+        if self.radio.lock_channel(finished.previous_channel).is_err() {
+            error!("failed to return to channel {} after scanning", finished.previous_channel);
+        }
+        self.radio.set_filter_status(RxFilterBank::BSSID, true);
+        info!("scan finished, found {} hosts", finished.results.len());
+        if let Some(request_id) = finished.request_id {
+            self.control_responder.send_response_to(request_id, ScanResults(finished.results));
+        }
+    }
+
+    async fn is_scanning(&self) -> bool {
+        self.scan.lock().await.is_some()
     }
 
     async fn send_beacon(&self,ticker: &mut Ticker) {
         ticker.next().await;
         {
             let enabled = self.beacons_enabled.lock().await;
-            if !*enabled {
+            if !*enabled || self.is_scanning().await {
                 return;
             }
         }
-        let mut buffer = self.interface_control.alloc_tx_buf().await;
-
-        //todo: move all this stuff to api
-        let beacon = {
-            let client_mananger = self.client_manager.lock().await;
-
-            DSWiFiBeaconTag {
-                oui_type: 0,
-                stepping_offset: [0x0a, 0x00],
-                lcd_video_sync: [0x00, 0x00],
-                fixed_id: [0x00, 0x00, 0x00, 0x0a],
-                game_id: [0x00, 0x00, 0x00, 0x00],
-                beacon_type: BeaconType::MULTICART,
-                cmd_data_size: 0x00c0,
-                reply_data_size: 0x00c0,
-                stream_code: 0x0f0f, //todo: increment this like a real ds
-                payload: Some(PictochatBeacon {
-                    chatroom: PictochatChatroom::B,
-                    client_count: client_mananger.all_clients_mask.num_clients() + 1,
-                    ..Default::default()
-                }),
-            }
+        let mut buffer = self.radio.alloc_tx_buf().await;
+
+        let polled_clients = self.client_manager.lock().await.all_clients_mask;
+        let mut contents = self.beacon_contents.lock().await;
+        if let Some(offset) = contents.client_count_offset {
+            // the host counts as a console too
+            contents.payload[offset] = polled_clients.num_clients() + 1;
+        }
+
+        // every polled client gets MP data each poll, so all of them have traffic waiting
+        let dtim_count = self.dtim_count.load(Ordering::Relaxed);
+        self.dtim_count.store(dtim_count.checked_sub(1).unwrap_or(self.dtim_period - 1), Ordering::Relaxed);
+        let tim = TrafficIndicationMap {
+            dtim_count,
+            dtim_period: self.dtim_period,
+            group_traffic: dtim_count == 0 && !polled_clients.is_empty(),
+            traffic_mask: polled_clients,
+        };
+        let mut tim_body = [0u8; TrafficIndicationMap::MAX_SIZE];
+        // the buffer fits the TIM of every possible mask
+        let Ok(tim_size) = tim_body.pwrite(tim, 0) else {
+            error!("failed to write the TIM");
+            return;
+        };
+
+        let beacon = DSWiFiBeaconTag {
+            oui_type: 0,
+            stepping_offset: [0x0a, 0x00],
+            lcd_video_sync: [0x00, 0x00],
+            fixed_id: [0x00, 0x00, 0x00, 0x0a],
+            game_id: contents.game_id,
+            beacon_type: contents.beacon_type,
+            cmd_data_size: contents.cmd_data_size,
+            reply_data_size: contents.reply_data_size,
+            stream_code: self.stream_code,
+            payload: if contents.payload.is_empty() { None } else { Some(contents.payload.as_slice()) },
         };
 
         let frame = BeaconFrame {
@@ -305,11 +474,11 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
                 receiver_address: BROADCAST,
                 transmitter_address: MACAddress::from(self.mac_address),
                 bssid: MACAddress::from(self.mac_address),
-                sequence_control: SequenceControl::new(),
+                sequence_control: self.next_mgmt_sequence_control(),
                 ..Default::default()
             },
             body: BeaconBody {
-                beacon_interval: 100,
+                beacon_interval: beacon_interval_tu(*self.beacon_interval.lock().await).unwrap_or(100),
                 timestamp: self.start_time.elapsed().as_micros(),
                 capabilities_info: CapabilitiesInformation::new().with_is_ess(true),
                 elements: element_chain! {
@@ -318,41 +487,92 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
                             2 B
                         ],
                     DSSSParameterSetElement {
-                        current_channel: 7
+                        current_channel: self.channel.load(Ordering::Relaxed)
                     },
                     RawIEEE80211Element {
-                        tlv_type: 5,
-                        slice: &[00u8,02,00,00],
+                        tlv_type: TrafficIndicationMap::ELEMENT_ID,
+                        slice: &tim_body[..tim_size],
                         _phantom: Default::default(),
                     },
-                    VendorSpecificElement::new_prefixed(&[0x00u8,0x09,0xbf],beacon)
+                    VendorSpecificElement::new_prefixed(NINTENDO_OUI.as_slice(),beacon)
                 },
                 _phantom: PhantomData
             },
         };
 
-        let written = buffer.pwrite_with(frame, 0, false).unwrap();
+        // DsWiFiBeaconContents::validate keeps the payload within what the vendor element can carry,
+        // so with the fixed size elements the beacon always fits a tx buffer
+        let Ok(written) = buffer.pwrite_with(frame, 0, false) else {
+            error!("failed to write the beacon");
+            return;
+        };
+        drop(contents);
 
-        let _ = self.interface_control.transmit(
+        let _ = self.radio.transmit(
             &mut buffer[..written],
             &TxParameters {
                 rate: WiFiRate::PhyRate2MS,
                 duration: 0,
                 tx_error_behaviour: TxErrorBehaviour::Drop,
-                override_seq_num: true,
+                override_seq_num: false,
                 tx_timeout: 0,
             }, false
         ).await;
 
     }
 
-    async fn send_deauth(&self, target: &[u8; 6]) {
-        //todo: this
+    async fn send_deauth(&self, target: &[u8; 6], reason: IEEE80211Reason) {
+        let mut buffer = self.radio.alloc_tx_buf().await;
+
+        let frame = DeauthenticationFrame {
+            header: ManagementFrameHeader {
+                receiver_address: MACAddress::from(*target),
+                transmitter_address: MACAddress::from(self.mac_address),
+                bssid: MACAddress::from(self.mac_address),
+                sequence_control: self.next_mgmt_sequence_control(),
+                ..Default::default()
+            },
+            body: DeauthenticationBody {
+                reason,
+                elements: element_chain!(),
+                _phantom: Default::default()
+            },
+        };
+
+        let Ok(written) = buffer.pwrite_with(frame, 0, false) else {
+            error!("failed to write the deauthentication");
+            return;
+        };
+
+        // the client may well be gone already, so don't try too hard
+        if let Err(_) = self.radio.transmit(
+            &mut buffer[..written],
+            &TxParameters {
+                rate: WiFiRate::PhyRate2MS,
+                duration: acked_frame_duration(WiFiRate::PhyRate2MS),
+                tx_error_behaviour: TxErrorBehaviour::RetryUntil(4),
+                override_seq_num: false,
+                tx_timeout: 10,
+            },
+            true
+        ).await {
+            warn!("deauth to {:?} was not acked", target);
+        }
+    }
+
+    // Deauthenticates a client on request of the application.
+    async fn kick_client(&self, mac: [u8; 6]) -> Result<(), DsWiFiControlError> {
+        self.client_manager.lock().await.update_client_state(MACAddress::from(mac), DsWiFiClientState::Leaving)?;
+        self.send_deauth(&mac, IEEE80211Reason::Unspecified).await;
+        self.remove_client_by_mac(MACAddress::from(mac)).await;
+        Ok(())
     }
-    async fn send_ack(&self) {
+    // Closes an MP exchange, `replied` are the clients whose reply came in.
+    async fn send_ack(&self, replied: DsWifiClientMask) {
         let tx = Instant::now();
 
-        let ack = hex!("82000000");
+        let mut ack = hex!("82000000");
+        ack[2..].copy_from_slice(&replied.to_le_bytes());
         let frame = DataFrame {
             header: DataFrameHeader {
                 subtype: DataFrameSubtype::DataCFAck,
@@ -361,7 +581,7 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
                 address_1: MACAddress::from([0x03,0x09,0xbf,0x00,0x00,0x03]),
                 address_2: MACAddress::from(self.mac_address),
                 address_3: MACAddress::from(self.mac_address),
-                sequence_control: Default::default(),
+                sequence_control: self.next_data_sequence_control(),
                 address_4: None,
                 qos: None,
                 ht_control: None,
@@ -369,63 +589,80 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
             payload: Some(ack.as_slice()),
             _phantom: Default::default(),
         };
-        let mut buffer = self.interface_control.alloc_tx_buf().await;
+        let mut buffer = self.radio.alloc_tx_buf().await;
 
-        let written = buffer.pwrite_with(frame, 0, false).unwrap();
+        let Ok(written) = buffer.pwrite_with(frame, 0, false) else {
+            error!("failed to write the MP ack");
+            return;
+        };
 
         //info!("ack2 delay: {}", (Instant::now() - tx).as_micros());
-        let _ = self.interface_control.transmit(
+        let _ = self.radio.transmit(
             &mut buffer[..written],
             &TxParameters {
-                rate: WiFiRate::PhyRate2MS,
+                rate: MP_RATE,
                 duration: 0,
                 tx_error_behaviour: TxErrorBehaviour::Drop,
-                override_seq_num: true,
+                override_seq_num: false,
                 tx_timeout: 0,
             }, false
         ).await;
     }
     async fn handle_timeouts(&self, ticker: &mut Ticker) {
         ticker.next().await;
-        let mut timed_clients: [Option<AssociationID>; MAX_CLIENTS] = [None; MAX_CLIENTS];
-        let mut i = 0;
-        let mut client_manager = self.client_manager.lock().await;
-        for client in &client_manager.clients {
-            if let Some(client) = client {
-                if client.last_heard_from.elapsed() > Duration::from_secs(1) {
-                    info!("client {:?} timed out", client.associated_mac_address);
-                    self.send_deauth(&client.associated_mac_address).await;
-                    self.event_tx.send(DsWiFiClientEvent::Disconnected(client.associated_mac_address)).await;
-
-                    timed_clients[i] = Some(client.association_id);
-                    i+=1;
-                }
+        // the client manager stays unlocked while the deauths go out, they wait for an ack each
+        let mut timed_out: [Option<[u8; 6]>; MAX_CLIENTS] = [None; MAX_CLIENTS];
+        {
+            let mut client_manager = self.client_manager.lock().await;
+            let expired = client_manager.clients.iter().flatten().filter(|client| client.timed_out(client_manager.state_timeout(client.state)));
+            for (slot, client) in timed_out.iter_mut().zip(expired) {
+                info!("client {:?} timed out in state {:?}", client.associated_mac_address, client.state);
+                *slot = Some(client.associated_mac_address);
             }
-        };
-        for client in timed_clients {
-            if let Some(client) = client {
-                client_manager.remove_client(client);
+            for mac in timed_out.iter().flatten() {
+                let mac = MACAddress::from(*mac);
+                // clients that were already leaving just get removed
+                if client_manager.get_client(mac).is_some_and(|client| client.state != DsWiFiClientState::Leaving) {
+                    if let Err(e) = client_manager.update_client_state(mac, DsWiFiClientState::Leaving) {
+                        warn!("failed to mark client as leaving: {:?}", e);
+                    }
+                }
             }
         }
+        for mac in timed_out.into_iter().flatten() {
+            self.send_deauth(&mac, IEEE80211Reason::Inactivity).await;
+            self.remove_client_by_mac(MACAddress::from(mac)).await;
+        }
     }
 
     async fn send_data_tick(&self, ticker: &mut Ticker) {
         ticker.next().await;
 
+        if self.is_scanning().await {
+            return;
+        }
 
         let mut mask = {
             let client_manager = self.client_manager.lock().await;
 
-            if client_manager.all_clients_mask.is_empty() {
+            let errored_mask = if self.mp_poll_count.fetch_add(1, Ordering::Relaxed) % ERRORED_POLL_INTERVAL == 0 {
+                client_manager.errored_clients_mask()
+            } else {
+                0
+            };
+            if client_manager.all_clients_mask.is_empty() && errored_mask.is_empty() {
                 return;
             }
 
-            if !client_manager.current_mask.is_empty() {
+            let mut mask = if !client_manager.current_mask.is_empty() {
                 client_manager.current_mask.clone()
             } else {
-                warn!("frame generation failed");
+                if !client_manager.all_clients_mask.is_empty() {
+                    warn!("frame generation failed");
+                }
                 client_manager.all_clients_mask.clone()
-            }
+            };
+            mask.mask_add(errored_mask)
         };
 
 
@@ -433,7 +670,14 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
 
         //info!("sending data frame with payload size {}", payload.size);
 
-        let max_client_ack_wait_micros = 998;
+        let reply_data_size = self.beacon_contents.lock().await.reply_data_size;
+        // DsWiFiBeaconContents::validate keeps the slot and the window within the frame fields
+        let us_per_client_reply = mp_reply_slot_micros(MP_RATE, reply_data_size);
+        let reply_window_micros = us_per_client_reply * mask.num_clients() as u32;
+        // retries of a frame keep its sequence number, it only moves on once the frame is done
+        let data_seq = self.data_seq.load(Ordering::Relaxed);
+        let payload_size = payload.size;
+        let retries = payload.retries;
 
         let frame = DataFrame {
             header: DataFrameHeader {
@@ -443,36 +687,45 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
                 address_1: MACAddress::from([0x03,0x09,0xbf,0x00,0x00,0x00]),
                 address_2: MACAddress::from(self.mac_address),
                 address_3: MACAddress::from(self.mac_address),
-                sequence_control: SequenceControl::new(),
+                sequence_control: self.next_data_sequence_control(),
                 address_4: None,
                 qos: None,
                 ht_control: None,
             },
             payload: Some(HostToClientDataFrame::<&[u8]> {
-                us_per_client_reply: max_client_ack_wait_micros,
+                us_per_client_reply: us_per_client_reply as u16,
                 client_target_mask: mask,
                 flags: payload.flags,
                 payload: if payload.size != 0 { Some(&payload.data[..payload.size as usize]) } else { None },
                 footer: Some(HostToClientFooter {
-                    data_seq: self.data_seq.fetch_add(1,Ordering::Relaxed),
+                    data_seq,
                     client_target_mask: mask,
                 }),
             }),
             _phantom: Default::default(),
         };
 
-        let mut buffer = self.interface_control.alloc_tx_buf().await;
+        let mut buffer = self.radio.alloc_tx_buf().await;
 
-        let written  = buffer.pwrite_with(frame, 0, false).unwrap();
+        let Ok(written) = buffer.pwrite_with(frame, 0, false) else {
+            error!("failed to write the MP poll");
+            return;
+        };
+        // the application needs the frame buffer to generate the next frame
+        drop(payload);
 
         while self.ack_rx_queue.try_receive().is_ok() {
             warn!("ack received after timeout");
         }
 
         let tx_pre = Instant::now();
-        let res = self.interface_control.transmit(
+        let res = self.radio.transmit(
             &mut buffer[..written],
-            &ds_tx_params_for_dataframe(WiFiRate::PhyRate2MS, written, TxErrorBehaviour::RetryUntil(4)),
+            &ds_tx_params_for_dataframe(
+                MP_RATE,
+                clamp_duration(mp_poll_duration_micros(MP_RATE, reply_window_micros)),
+                TxErrorBehaviour::RetryUntil(4),
+            ),
             false
         ).await;
         let tx = Instant::now();
@@ -484,8 +737,19 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
             return;
         }
 
-        //TODO: this still isnt right, but it works most of the time
-        let mut timeout = Timer::after_micros(((max_client_ack_wait_micros * 5) * (mask.num_clients() as u16)) as u64);
+        {
+            let mut client_manager = self.client_manager.lock().await;
+            for client in client_manager.clients.iter_mut().flatten() {
+                if mask & client.association_id.get_mask_bits() != 0 {
+                    client.stats.bytes_out += payload_size as u64;
+                }
+            }
+        }
+
+        let mut timeout = Timer::at(tx + Duration::from_micros((reply_window_micros + MP_REPLY_GUARD_MICROS) as u64));
+        let polled = mask;
+        // reported after the exchange, sending events must not hold up the reply window
+        let mut connected: [Option<[u8; 6]>; MAX_CLIENTS] = [None; MAX_CLIENTS];
 
         while !mask.is_empty() {
             match select(&mut timeout,self.ack_rx_queue.receive()).await {
@@ -495,32 +759,53 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
                     if let Some(client) = client_manager.get_client_mut(ack_from) {
                         let ack = Instant::now();
                         debug!("ack latency: {} / {}", (ack - tx).as_micros(), (ack - ack_enqueue_time).as_micros());
-                        Timer::after_micros(450).await;
-                        self.send_ack().await;
+                        client.stats.record_ack_latency(ack_enqueue_time.saturating_duration_since(tx));
                         client.last_heard_from = Instant::now();
+                        client.consecutive_misses = 0;
                         mask.mask_subtract(client.association_id.get_mask_bits());
+
+                        if matches!(client.state, DsWiFiClientState::Associated | DsWiFiClientState::Errored) {
+                            if let Err(e) = client_manager.update_client_state(ack_from, DsWiFiClientState::Connected) {
+                                warn!("failed to mark client as connected: {:?}", e);
+                            }
+                            if let Some(slot) = connected.iter_mut().find(|slot| slot.is_none()) {
+                                *slot = Some(*ack_from);
+                            }
+                        }
                     }
                 }
             }
         }
 
+        // a single ack closes the exchange once the replies are in or the window is over, like a
+        // real DS parent does, even if nobody replied
+        self.send_ack(polled & !mask).await;
+        for mac in connected.into_iter().flatten() {
+            self.event_tx.send(DsWiFiClientEvent::Connected(mac)).await;
+        }
+
         trace!("mask {:?}",mask);
+        if !mask.is_empty() {
+            mask = self.handle_missed_poll(mask, data_seq, retries + 1).await;
+        }
+
         if mask.is_empty() {
+            self.data_tx_mutex.lock().await.retries = 0;
+            self.data_seq.fetch_add(1, Ordering::Relaxed);
+
             debug!("signalling FrameRequired");
             self.data_tx_signal.signal(FrameRequired);
 
             self.data_tx_signal_2.wait().await;
             self.data_tx_signal_2.reset();
 
+        } else {
+            self.data_tx_mutex.lock().await.retries = retries + 1;
         }
 
         {
             let mut client_manager = self.client_manager.lock().await;
 
-            if client_manager.all_clients_mask.is_empty() {
-                return;
-            }
-
              if mask.is_empty() {
                  client_manager.current_mask = client_manager.all_clients_mask;
              } else {
@@ -529,20 +814,187 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
         }
 
     }
+    // Counts a miss for every client that didn't answer the poll and marks the ones over the miss
+    // limit as errored. Returns the clients the frame should be retried for, which is empty once
+    // the frame ran out of retries. `retries` is how often the frame was sent before plus this poll.
+    async fn handle_missed_poll(&self, missed: DsWifiClientMask, data_seq: u16, retries: u8) -> DsWifiClientMask {
+        let retry_limits = *self.retry_limits.lock().await;
+        let mut client_manager = self.client_manager.lock().await;
+
+        let mut errored: [Option<MACAddress>; MAX_CLIENTS] = [None; MAX_CLIENTS];
+        for (i, client) in client_manager.clients.iter_mut().enumerate() {
+            let Some(client) = client else {
+                continue;
+            };
+            if missed & client.association_id.get_mask_bits() == 0 {
+                continue;
+            }
+            client.consecutive_misses = client.consecutive_misses.saturating_add(1);
+            client.stats.missed_polls = client.stats.missed_polls.saturating_add(1);
+            // errored clients were already reported, they're only polled in case they come back
+            if client.state == DsWiFiClientState::Errored {
+                continue;
+            }
+            if let Err(_) = self.event_tx.try_send(DsWiFiClientEvent::FrameMissed(client.associated_mac_address, data_seq)) {
+                warn!("event queue full, dropping FrameMissed");
+            }
+            if client.consecutive_misses >= retry_limits.max_consecutive_misses {
+                errored[i] = Some(MACAddress::from(client.associated_mac_address));
+            }
+        }
+        for mac in errored.into_iter().flatten() {
+            warn!("client {:?} stopped answering polls", *mac);
+            if let Err(e) = client_manager.update_client_state(mac, DsWiFiClientState::Errored) {
+                warn!("failed to mark client as errored: {:?}", e);
+            }
+        }
+
+        if retries >= retry_limits.max_frame_retries {
+            warn!("giving up on frame {} after {} retries", data_seq, retries);
+            return 0;
+        }
+        // frames aren't resent for errored clients
+        let retry_mask = missed & client_manager.all_clients_mask;
+        for client in client_manager.clients.iter_mut().flatten() {
+            if retry_mask & client.association_id.get_mask_bits() != 0 {
+                client.stats.retries = client.stats.retries.saturating_add(1);
+            }
+        }
+        retry_mask
+    }
+
+    // Deauthenticates every client, used when they can't follow us anyway.
+    async fn disconnect_all_clients(&self, reason: IEEE80211Reason) {
+        let mut leaving: [Option<[u8; 6]>; MAX_CLIENTS] = [None; MAX_CLIENTS];
+        {
+            let mut client_manager = self.client_manager.lock().await;
+            for (slot, client) in leaving.iter_mut().zip(client_manager.clients.iter().flatten()) {
+                *slot = Some(client.associated_mac_address);
+            }
+            for mac in leaving.iter().flatten() {
+                if let Err(e) = client_manager.update_client_state(MACAddress::from(*mac), DsWiFiClientState::Leaving) {
+                    warn!("failed to mark client as leaving: {:?}", e);
+                }
+            }
+        }
+        for mac in leaving.into_iter().flatten() {
+            self.send_deauth(&mac, reason).await;
+            self.remove_client_by_mac(MACAddress::from(mac)).await;
+        }
+    }
+
+    // The radio is moved first, so the clients stay connected if it can't switch. They can't follow
+    // a channel change, so they are deauthenticated afterwards and have to find us again through
+    // the beacons, which advertise the new channel once it's stored.
+    async fn set_channel(&self, channel: u8) -> Result<(), DsWiFiControlError> {
+        if !is_valid_channel(channel) {
+            return Err(DsWiFiControlError::InvalidParameter("channel"));
+        }
+        if self.is_scanning().await {
+            return Err(DsWiFiControlError::Scanning);
+        }
+        if self.channel.load(Ordering::Relaxed) == channel {
+            return Ok(());
+        }
+        self.radio.lock_channel(channel)?;
+        self.disconnect_all_clients(IEEE80211Reason::Unspecified).await;
+        self.channel.store(channel, Ordering::Relaxed);
+        info!("moved to channel {}", channel);
+        Ok(())
+    }
+
+    async fn stats(&self) -> DsWiFiInterfaceStats {
+        DsWiFiInterfaceStats {
+            channel: self.channel.load(Ordering::Relaxed),
+            tx_power: self.tx_power.load(Ordering::Relaxed),
+            beacon_interval: *self.beacon_interval.lock().await,
+            mp_poll_interval: *self.mp_poll_interval.lock().await,
+            accepting_clients: self.accepting_clients.load(Ordering::Relaxed),
+            client_count: self.client_manager.lock().await.clients.iter().flatten().count() as u8,
+            dropped_frames: self.dropped_frames.load(Ordering::Relaxed),
+            data_seq: self.data_seq.load(Ordering::Relaxed),
+        }
+    }
+
     async fn handle_control(&self) {
         let request = self.control_responder.wait_for_request().await;
-        match request {
+        // requesters wait for their answer one at a time, so the one behind a running scan gave up
+        // on it and the results must not end up as the answer to this request
+        if let Some(scan) = self.scan.lock().await.as_mut() {
+            if scan.request_id.take().is_some() {
+                debug!("scan request was abandoned, results won't be reported");
+            }
+        }
+        let result = match request {
             DsWiFiInterfaceControlEvent::SetChannel(channel) => {
-                if let Err(_) = self.interface_control.lock_channel(channel) {
-                    self.control_responder.send_response(Failed);
-                } else {
-                    self.control_responder.send_response(Success);
-                }
+                self.set_channel(channel).await.map(|_| Success)
             },
             DsWiFiInterfaceControlEvent::SetBeaconsEnabled(new_enabled) => {
                 let mut enabled = self.beacons_enabled.lock().await;
                 *enabled = new_enabled;
-                self.control_responder.send_response(Success);
+                Ok(Success)
+            }
+            DsWiFiInterfaceControlEvent::SetRetryLimits(retry_limits) => {
+                *self.retry_limits.lock().await = retry_limits;
+                Ok(Success)
+            }
+            DsWiFiInterfaceControlEvent::ListClients => {
+                let client_manager = self.client_manager.lock().await;
+                Ok(Clients(client_manager.clients.iter().flatten().map(DsWiFiClient::snapshot).collect()))
+            }
+            DsWiFiInterfaceControlEvent::KickClient(mac) => {
+                self.kick_client(mac).await.map(|_| Success)
+            }
+            DsWiFiInterfaceControlEvent::SetTxPower(tx_power) => {
+                self.radio.set_tx_power(tx_power);
+                self.tx_power.store(tx_power, Ordering::Relaxed);
+                Ok(Success)
+            }
+            DsWiFiInterfaceControlEvent::SetBeaconInterval(interval) => {
+                if beacon_interval_tu(interval).is_none() {
+                    Err(DsWiFiControlError::InvalidParameter("beacon interval"))
+                } else {
+                    *self.beacon_interval.lock().await = interval;
+                    Ok(Success)
+                }
+            }
+            DsWiFiInterfaceControlEvent::SetMpPollInterval(interval) => {
+                if interval.as_ticks() == 0 {
+                    Err(DsWiFiControlError::InvalidParameter("mp poll interval"))
+                } else {
+                    *self.mp_poll_interval.lock().await = interval;
+                    Ok(Success)
+                }
+            }
+            DsWiFiInterfaceControlEvent::SetBeaconContents(contents) => {
+                match contents.validate() {
+                    Ok(()) => {
+                        *self.beacon_contents.lock().await = contents;
+                        Ok(Success)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            DsWiFiInterfaceControlEvent::SetAcceptingClients(accepting) => {
+                self.accepting_clients.store(accepting, Ordering::Relaxed);
+                Ok(Success)
+            }
+            DsWiFiInterfaceControlEvent::GetStats => {
+                Ok(Stats(self.stats().await))
+            }
+            DsWiFiInterfaceControlEvent::ScanForHosts(dwell_time) => {
+                // the response is sent by scan_tick once all channels were visited
+                match self.start_scan(dwell_time).await {
+                    Ok(()) => return,
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        match result {
+            Ok(response) => self.control_responder.send_response(response),
+            Err(e) => {
+                warn!("control request failed: {:?}", e);
+                self.control_responder.send_response(Failed(e));
             }
         }
     }
@@ -552,13 +1004,14 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
             self.send_data_tick(data_rate_limit),
             self.send_beacon(beacon_ticker),
             self.handle_timeouts(timeout_check_rate),
-            self.handle_control()
+            self.scan_tick()
         ).await;
     }
 
-    async fn interface_input(&self, borrowed_buffer: BorrowedBuffer<'foa>) {
+    async fn interface_input(&self, borrowed_buffer: R::RxBuffer) {
         //info!("InterfaceInput: {} {:x}",borrowed_buffer.rssi(), borrowed_buffer.mpdu_buffer());
         let rx = Instant::now();
+        let rssi = borrowed_buffer.rssi();
         let Ok(generic_frame) = GenericFrame::new(borrowed_buffer.mpdu_buffer(), false) else {
             return;
         };
@@ -572,30 +1025,42 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
             FrameType::Data(data) => {
                 match data {
                     DataFrameSubtype::DataCFAck => {
-                        //TODO: parse frame and extract the app payload (if present) and forward it to control layer
-                        let frame = generic_frame.parse_to_typed::<DataFrame>().unwrap().unwrap();
-                        match frame.payload.unwrap() {
-                            DataFrameReadPayload::Single(data) => {
-                                let (c2h_frame,size) = ClientToHostDataFrame::try_from_ctx(data, ()).unwrap();
-                                let rx_ack = Instant::now();
-                                //info!("ack delay: {}", (rx_ack - rx).as_micros());
-                                if c2h_frame.payload_size > 0 {
-                                    let (frame,size) = c2h_frame.payload.unwrap();
-                                    self.data_rx_queue_sender.try_send((frame,generic_frame.address_2().unwrap(),size)).expect("todo")
-                                }
+                        // the reply follows the header, which is as far as the header parser read
+                        let (Some(transmitter), Ok((_, reply_offset))) = (generic_frame.address_2(), DataFrameHeader::try_from_ctx(borrowed_buffer.mpdu_buffer(), ())) else {
+                            warn!("dropping malformed data frame");
+                            self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+                            return;
+                        };
+                        let payload_size = match ClientToHostDataFrame::try_from_ctx(&borrowed_buffer.mpdu_buffer()[reply_offset..], ()) {
+                            Ok((reply, _)) => reply.payload.map_or(0, |payload| payload.len()),
+                            Err(e) => {
+                                warn!("dropping malformed reply from {:?}: {:?}", transmitter, e);
+                                self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+                                return;
+                            }
+                        };
+                        // the rx buffer itself gets handed to the application
+                        if payload_size > 0 {
+                            if let Err(_) = self.data_rx_queue_sender.try_send(DsWiFiReceivedData::new(transmitter, borrowed_buffer, reply_offset)) {
+                                // still ack it below, the console would just keep resending otherwise
+                                warn!("data queue full, dropping reply");
+                                self.dropped_frames.fetch_add(1, Ordering::Relaxed);
                             }
-                            DataFrameReadPayload::AMSDU(_) => {}
                         }
-                        if let Err(_) = self.ack_rx_queue_sender.try_send((generic_frame.address_2().unwrap(),Instant::now())) {
+                        if let Err(_) = self.ack_rx_queue_sender.try_send((transmitter,Instant::now())) {
                             error!("Failed to send ack to runner");
                         }
+                        self.record_client_rx(transmitter, rssi, payload_size).await;
                     }
                     DataFrameSubtype::CFAck => {
-
-                        if let Err(_) = self.ack_rx_queue_sender.try_send((generic_frame.address_2().unwrap(),Instant::now())) {
+                        let Some(transmitter) = generic_frame.address_2() else {
+                            self.dropped_frames.fetch_add(1, Ordering::Relaxed);
+                            return;
+                        };
+                        if let Err(_) = self.ack_rx_queue_sender.try_send((transmitter,Instant::now())) {
                             error!("Failed to send ack to runner");
                         }
-
+                        self.record_client_rx(transmitter, rssi, 0).await;
                     }
 
                     _ => {
@@ -609,35 +1074,105 @@ impl<'foa> DsWiFiRunner<'_,'foa> {
         }
     }
 
+    // Listens on every DS channel for `dwell_time` before hosting and moves to the quietest one.
+    async fn select_channel(&self, dwell_time: Duration) {
+        let mut scan = DsHostScan::new(dwell_time, self.channel.load(Ordering::Relaxed));
+        // channels we couldn't switch to stay None and aren't considered
+        let mut surveys: [Option<DsChannelSurvey>; DS_CHANNELS.len()] = [None; DS_CHANNELS.len()];
+        self.radio.set_filter_status(RxFilterBank::BSSID, false);
+
+        loop {
+            if self.radio.lock_channel(scan.current_channel()).is_err() {
+                warn!("failed to switch to channel {} for the survey", scan.current_channel());
+            } else {
+                let survey = surveys[scan.channel_index].insert(DsChannelSurvey { channel: scan.current_channel(), ..Default::default() });
+                while let Either::Second(buffer) = select(Timer::at(scan.channel_deadline()), self.radio.receive()).await {
+                    survey.record_frame(buffer.rssi());
+                    let _ = match_frames! {
+                        buffer.mpdu_buffer(),
+                        beacon = BeaconFrame => {
+                            let bssid = *beacon.header.bssid;
+                            let elements = buffer.mpdu_buffer().get(BEACON_ELEMENTS_OFFSET..).unwrap_or_default();
+                            if bssid != self.mac_address {
+                                if let Some(host) = DsHostInfo::from_beacon_elements(bssid, scan.current_channel(), buffer.rssi(), elements) {
+                                    scan.record(host);
+                                }
+                            }
+                        }
+                    };
+                }
+            }
+            if !scan.next_channel() {
+                break;
+            }
+        }
+
+        self.radio.set_filter_status(RxFilterBank::BSSID, true);
+        for survey in surveys.iter_mut().flatten() {
+            survey.ds_hosts = scan.results.iter().filter(|host| host.channel == survey.channel).count() as u8;
+            info!("channel {}: {} frames, {} ds hosts, strongest rssi {:?}", survey.channel, survey.frames, survey.ds_hosts, survey.strongest_rssi);
+        }
+
+        let quietest = surveys.iter().flatten().min_by_key(|survey| survey.occupancy()).map_or(scan.previous_channel, |survey| survey.channel);
+        if self.radio.lock_channel(quietest).is_ok() {
+            self.channel.store(quietest, Ordering::Relaxed);
+            info!("hosting on channel {}", quietest);
+        } else if self.radio.lock_channel(scan.previous_channel).is_err() {
+            error!("failed to return to channel {} after the survey", scan.previous_channel);
+        }
+    }
+
     pub async fn run(&mut self) -> ! {
         info!("Runner Says Hi");
 
-        let mut beacon_ticker = Ticker::every(Duration::from_millis(100));
+        if let Some(dwell_time) = self.auto_channel_dwell_time {
+            self.select_channel(dwell_time).await;
+        }
+
+        let mut beacon_interval = *self.beacon_interval.lock().await;
+        let mut mp_poll_interval = *self.mp_poll_interval.lock().await;
+        let mut beacon_ticker = Ticker::every(beacon_interval);
         let mut timeout_check_rate = Ticker::every(Duration::from_secs(2));
-        let mut data_rate_limit = Ticker::every(Duration::from_millis(33)); //very slow rate limit for now
+        let mut data_rate_limit = Ticker::every(mp_poll_interval);
 
         join!(
             async {
                 loop {
-                    let data = self.interface_rx_queue.receive().await;
+                    let data = self.radio.receive().await;
                     self.interface_input(data).await;
                 }
             },
             async {
                 loop {
                     match select3(
-                        self.interface_control.wait_for_off_channel_request(),
+                        self.radio.reject_off_channel_request(),
                         self.bg_rx_queue.receive(),
                         self.tick(&mut beacon_ticker,
                                   &mut data_rate_limit,
                                   &mut timeout_check_rate),
                     ).await {
-                        Either3::First(off_channel_request) => {
-                            off_channel_request.reject();
-                        },
+                        Either3::First(_) => {},
                         Either3::Second(buffer) => {self.handle_bg_rx(buffer).await;},
                         _ => {}
                     }
+
+                    let new_beacon_interval = *self.beacon_interval.lock().await;
+                    if new_beacon_interval != beacon_interval {
+                        beacon_interval = new_beacon_interval;
+                        beacon_ticker = Ticker::every(beacon_interval);
+                    }
+                    let new_mp_poll_interval = *self.mp_poll_interval.lock().await;
+                    if new_mp_poll_interval != mp_poll_interval {
+                        mp_poll_interval = new_mp_poll_interval;
+                        data_rate_limit = Ticker::every(mp_poll_interval);
+                    }
+                }
+            },
+            // control requests get their own loop, tick is restarted all the time and would drop
+            // a request that is halfway done
+            async {
+                loop {
+                    self.handle_control().await;
                 }
             }
         ).await;
@@ -0,0 +1,127 @@
+use alloc::vec::Vec;
+use embassy_time::{Duration, Instant};
+use ieee80211::scroll::Pread;
+use crate::packets::{raw_elements, BeaconType, DSWiFiBeaconTag};
+use crate::pictochat_packets::{PictochatBeacon, PictochatChatroom};
+
+// Passive scan for other DS hosts, the runner hops over the DS channels and collects every
+// Nintendo beacon it hears.
+
+pub const DS_CHANNELS: [u8; 3] = [1, 7, 13];
+
+// management header + timestamp, beacon interval and capabilities
+pub(crate) const BEACON_ELEMENTS_OFFSET: usize = 24 + 8 + 2 + 2;
+const DSSS_PARAMETER_SET_ELEMENT_ID: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PictochatHostInfo {
+    pub chatroom: PictochatChatroom,
+    pub client_count: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DsHostInfo {
+    pub bssid: [u8; 6],
+    pub channel: u8,
+    pub rssi: i8,
+    pub game_id: [u8; 4],
+    pub beacon_type: BeaconType,
+    pub pictochat: Option<PictochatHostInfo>,
+}
+
+impl DsHostInfo {
+    pub(crate) fn from_beacon_elements(bssid: [u8; 6], channel: u8, rssi: i8, elements: &[u8]) -> Option<Self> {
+        let tag = DSWiFiBeaconTag::find_in_elements(elements)?;
+        // the channel the host advertises is more accurate than the one we heard it on
+        let channel = raw_elements(elements)
+            .find(|(element_id, body)| *element_id == DSSS_PARAMETER_SET_ELEMENT_ID && body.len() == 1)
+            .map_or(channel, |(_, body)| body[0]);
+        let pictochat = tag.payload
+            .and_then(|payload| payload.pread::<PictochatBeacon>(0).ok())
+            .map(|beacon| PictochatHostInfo {
+                chatroom: beacon.chatroom,
+                client_count: beacon.client_count,
+            });
+
+        Some(Self {
+            bssid,
+            channel,
+            rssi,
+            game_id: tag.game_id,
+            beacon_type: tag.beacon_type,
+            pictochat,
+        })
+    }
+}
+
+// What the start-up channel survey heard on one channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DsChannelSurvey {
+    pub channel: u8,
+    pub frames: u32,
+    pub strongest_rssi: Option<i8>,
+    pub ds_hosts: u8,
+}
+
+impl DsChannelSurvey {
+    pub(crate) fn record_frame(&mut self, rssi: i8) {
+        self.frames = self.frames.saturating_add(1);
+        self.strongest_rssi = Some(self.strongest_rssi.map_or(rssi, |strongest| strongest.max(rssi)));
+    }
+
+    // Lower is quieter. Other DS rooms matter most since their MP traffic collides with ours, then
+    // general traffic, and the signal strength only breaks ties.
+    pub fn occupancy(&self) -> (u8, u32, Option<i8>) {
+        (self.ds_hosts, self.frames, self.strongest_rssi)
+    }
+}
+
+pub(crate) struct DsHostScan {
+    pub(crate) dwell_time: Duration,
+    pub(crate) channel_index: usize,
+    pub(crate) channel_started: Instant,
+    pub(crate) previous_channel: u8,
+    pub(crate) results: Vec<DsHostInfo>,
+    // the control request the results answer, None for scans the runner started itself
+    pub(crate) request_id: Option<u32>,
+}
+
+impl DsHostScan {
+    pub(crate) fn new(dwell_time: Duration, previous_channel: u8) -> Self {
+        Self {
+            dwell_time,
+            channel_index: 0,
+            channel_started: Instant::now(),
+            previous_channel,
+            results: Vec::new(),
+            request_id: None,
+        }
+    }
+
+    pub(crate) fn current_channel(&self) -> u8 {
+        DS_CHANNELS[self.channel_index]
+    }
+
+    pub(crate) fn channel_deadline(&self) -> Instant {
+        self.channel_started + self.dwell_time
+    }
+
+    // Moves on to the next channel, returns false once all channels were visited.
+    pub(crate) fn next_channel(&mut self) -> bool {
+        self.channel_index += 1;
+        self.channel_started = Instant::now();
+        self.channel_index < DS_CHANNELS.len()
+    }
+
+    // A host is often heard on neighbouring channels as well, keep the strongest sighting.
+    pub(crate) fn record(&mut self, host: DsHostInfo) {
+        if let Some(known) = self.results.iter_mut().find(|known| known.bssid == host.bssid) {
+            if host.rssi >= known.rssi {
+                *known = host;
+            }
+        } else {
+            self.results.push(host);
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::pin;
+use core::ptr;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use embassy_time::{Duration, Instant, MockDriver, Timer};
+use ieee80211::common::{CapabilitiesInformation, DataFrameSubtype, FCFFlags, FrameType, IEEE80211AuthenticationAlgorithmNumber, IEEE80211Reason, IEEE80211StatusCode, SequenceControl};
+use ieee80211::data_frame::header::DataFrameHeader;
+use ieee80211::data_frame::{DataFrame, DataFrameReadPayload};
+use ieee80211::mac_parser::MACAddress;
+use ieee80211::mgmt_frame::body::{AssociationRequestBody, AuthenticationBody, DeauthenticationBody};
+use ieee80211::mgmt_frame::{AssociationRequestFrame, AssociationResponseFrame, AuthenticationFrame, BeaconFrame, DeauthenticationFrame, ManagementFrameHeader};
+use ieee80211::scroll::ctx::TryFromCtx;
+use ieee80211::scroll::Pwrite;
+use ieee80211::{element_chain, match_frames, supported_rates, GenericFrame};
+use crate::memory_radio::{MemoryRadio, MemoryTxFrame};
+use crate::packets::{ClientToHostDataFrame, ClientToHostFlags, HostToClientDataFrame};
+use crate::{DsWifiClientMask, DsWifiClientMaskMath};
+
+// Plays one or more DS consoles against a DsWiFiRunner that uses the MemoryRadio.
+// Everything is driven by the embassy mock time driver, so runs are fully deterministic.
+
+const SIM_FRAME_BUFFER_SIZE: usize = 1600;
+const DS_REPLY_ADDRESS: [u8; 6] = [0x03, 0x09, 0xbf, 0x00, 0x00, 0x10];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulatedConsoleState {
+    Idle,
+    WaitingForBeacon,
+    Authenticating,
+    Associating,
+    Connected,
+}
+
+pub struct SimulatedConsole {
+    pub mac_address: [u8; 6],
+    pub state: SimulatedConsoleState,
+    pub association_id: Option<u16>,
+    // a silent console stays associated but stops answering MP polls
+    pub silent: bool,
+    pub reply_payload: Vec<u8>,
+    pub polls_answered: u32,
+    pub times_deauthed: u32,
+}
+
+impl SimulatedConsole {
+    pub fn new(mac_address: [u8; 6]) -> Self {
+        Self {
+            mac_address,
+            state: SimulatedConsoleState::Idle,
+            association_id: None,
+            silent: false,
+            reply_payload: Vec::new(),
+            polls_answered: 0,
+            times_deauthed: 0,
+        }
+    }
+}
+
+// An MP poll as the consoles heard it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulatedMpPoll {
+    pub us_per_client_reply: u16,
+    // the Duration/ID the host reserved the medium with
+    pub duration: u16,
+    pub client_target_mask: DsWifiClientMask,
+}
+
+pub struct DsSimulator<'a> {
+    radio: &'a MemoryRadio,
+    host_mac_address: [u8; 6],
+    consoles: RefCell<Vec<SimulatedConsole>>,
+    mp_polls: RefCell<Vec<SimulatedMpPoll>>,
+    // the replied mask of every MP ack
+    mp_acks: RefCell<Vec<DsWifiClientMask>>,
+    pub rssi: i8,
+}
+
+impl<'a> DsSimulator<'a> {
+    pub fn new(radio: &'a MemoryRadio, host_mac_address: [u8; 6]) -> Self {
+        Self {
+            radio,
+            host_mac_address,
+            consoles: RefCell::new(Vec::new()),
+            mp_polls: RefCell::new(Vec::new()),
+            mp_acks: RefCell::new(Vec::new()),
+            rssi: -40,
+        }
+    }
+
+    pub fn add_console(&self, console: SimulatedConsole) -> usize {
+        let mut consoles = self.consoles.borrow_mut();
+        consoles.push(console);
+        consoles.len() - 1
+    }
+
+    pub fn with_console<T>(&self, index: usize, f: impl FnOnce(&mut SimulatedConsole) -> T) -> T {
+        f(&mut self.consoles.borrow_mut()[index])
+    }
+
+    pub fn console_state(&self, index: usize) -> SimulatedConsoleState {
+        self.consoles.borrow()[index].state
+    }
+
+    // The MP polls heard since the last call.
+    pub fn take_mp_polls(&self) -> Vec<SimulatedMpPoll> {
+        self.mp_polls.take()
+    }
+
+    // The replied masks of the MP acks heard since the last call.
+    pub fn take_mp_acks(&self) -> Vec<DsWifiClientMask> {
+        self.mp_acks.take()
+    }
+
+    // The console joins the next time it hears a beacon from the host.
+    pub fn join(&self, index: usize) {
+        self.with_console(index, |console| console.state = SimulatedConsoleState::WaitingForBeacon);
+    }
+
+    pub fn set_silent(&self, index: usize, silent: bool) {
+        self.with_console(index, |console| console.silent = silent);
+    }
+
+    pub fn deauth(&self, index: usize) {
+        let mac_address = self.with_console(index, |console| {
+            console.state = SimulatedConsoleState::Idle;
+            console.association_id = None;
+            console.mac_address
+        });
+        let frame = DeauthenticationFrame {
+            header: self.header_to_host(mac_address),
+            body: DeauthenticationBody {
+                reason: IEEE80211Reason::LeavingNetworkDeauth,
+                elements: element_chain!(),
+                _phantom: PhantomData,
+            },
+        };
+        let mut buffer = vec![0u8; SIM_FRAME_BUFFER_SIZE];
+        let written = buffer.pwrite_with(frame, 0, false).unwrap();
+        self.inject(&buffer[..written]);
+    }
+
+    fn inject(&self, frame: &[u8]) {
+        if !self.radio.inject_rx(frame, self.rssi) {
+            warn!("simulator rx queue full, dropping frame");
+        }
+    }
+
+    fn header_to_host(&self, mac_address: [u8; 6]) -> ManagementFrameHeader {
+        ManagementFrameHeader {
+            receiver_address: MACAddress::from(self.host_mac_address),
+            transmitter_address: MACAddress::from(mac_address),
+            bssid: MACAddress::from(self.host_mac_address),
+            sequence_control: SequenceControl::new(),
+            ..Default::default()
+        }
+    }
+
+    fn send_auth(&self, mac_address: [u8; 6]) {
+        let frame = AuthenticationFrame {
+            header: self.header_to_host(mac_address),
+            body: AuthenticationBody {
+                authentication_algorithm_number: IEEE80211AuthenticationAlgorithmNumber::OpenSystem,
+                authentication_transaction_sequence_number: 1,
+                status_code: IEEE80211StatusCode::Success,
+                elements: element_chain!(),
+                _phantom: PhantomData,
+            },
+        };
+        let mut buffer = vec![0u8; SIM_FRAME_BUFFER_SIZE];
+        let written = buffer.pwrite_with(frame, 0, false).unwrap();
+        self.inject(&buffer[..written]);
+    }
+
+    fn send_assoc_req(&self, mac_address: [u8; 6]) {
+        let frame = AssociationRequestFrame {
+            header: self.header_to_host(mac_address),
+            body: AssociationRequestBody {
+                capabilities_info: CapabilitiesInformation::new()
+                    .with_is_ess(true)
+                    .with_is_short_preamble_allowed(true),
+                listen_interval: 1,
+                elements: element_chain! {
+                    supported_rates![
+                        1 B,
+                        2 B
+                    ]
+                },
+                _phantom: PhantomData,
+            },
+        };
+        let mut buffer = vec![0u8; SIM_FRAME_BUFFER_SIZE];
+        let written = buffer.pwrite_with(frame, 0, false).unwrap();
+        self.inject(&buffer[..written]);
+    }
+
+    fn send_mp_reply(&self, mac_address: [u8; 6], payload: &[u8]) {
+        let reply = ClientToHostDataFrame {
+            payload_size: payload.len() as u16,
+            flags: ClientToHostFlags::empty(),
+            payload: if payload.is_empty() { None } else { Some(payload) },
+            footer_seq_no: None,
+        };
+
+        let frame = DataFrame {
+            header: DataFrameHeader {
+                subtype: DataFrameSubtype::DataCFAck,
+                fcf_flags: FCFFlags::new().with_to_ds(true),
+                duration: 0,
+                address_1: MACAddress::from(self.host_mac_address),
+                address_2: MACAddress::from(mac_address),
+                address_3: MACAddress::from(DS_REPLY_ADDRESS),
+                sequence_control: SequenceControl::new(),
+                address_4: None,
+                qos: None,
+                ht_control: None,
+            },
+            payload: Some(reply),
+            _phantom: Default::default(),
+        };
+        let mut buffer = vec![0u8; SIM_FRAME_BUFFER_SIZE];
+        let written = buffer.pwrite_with(frame, 0, false).unwrap();
+        self.inject(&buffer[..written]);
+    }
+
+    fn handle_beacon(&self) {
+        let mut joining: Vec<[u8; 6]> = Vec::new();
+        for console in self.consoles.borrow_mut().iter_mut() {
+            if console.state == SimulatedConsoleState::WaitingForBeacon {
+                console.state = SimulatedConsoleState::Authenticating;
+                joining.push(console.mac_address);
+            }
+        }
+        for mac_address in joining {
+            self.send_auth(mac_address);
+        }
+    }
+
+    fn handle_auth(&self, receiver: MACAddress, status_code: IEEE80211StatusCode) {
+        let mut associating = None;
+        for console in self.consoles.borrow_mut().iter_mut() {
+            if MACAddress::from(console.mac_address) == receiver && console.state == SimulatedConsoleState::Authenticating {
+                if status_code == IEEE80211StatusCode::Success {
+                    console.state = SimulatedConsoleState::Associating;
+                    associating = Some(console.mac_address);
+                } else {
+                    console.state = SimulatedConsoleState::Idle;
+                }
+            }
+        }
+        if let Some(mac_address) = associating {
+            self.send_assoc_req(mac_address);
+        }
+    }
+
+    fn handle_assoc_resp(&self, receiver: MACAddress, status_code: IEEE80211StatusCode, association_id: Option<u16>) {
+        for console in self.consoles.borrow_mut().iter_mut() {
+            if MACAddress::from(console.mac_address) == receiver && console.state == SimulatedConsoleState::Associating {
+                if status_code == IEEE80211StatusCode::Success && association_id.is_some() {
+                    console.state = SimulatedConsoleState::Connected;
+                    console.association_id = association_id;
+                } else {
+                    console.state = SimulatedConsoleState::Idle;
+                }
+            }
+        }
+    }
+
+    fn handle_deauth(&self, receiver: MACAddress) {
+        for console in self.consoles.borrow_mut().iter_mut() {
+            if MACAddress::from(console.mac_address) == receiver {
+                console.state = SimulatedConsoleState::Idle;
+                console.association_id = None;
+                console.times_deauthed += 1;
+            }
+        }
+    }
+
+    // Replies in slot order, a console waits one us_per_client_reply for every targeted console
+    // with a lower association id.
+    async fn handle_mp_poll(&self, tx_time: Instant, us_per_client_reply: u16, client_target_mask: DsWifiClientMask) {
+        let mut replies: Vec<(u16, [u8; 6], Vec<u8>)> = Vec::new();
+        for console in self.consoles.borrow_mut().iter_mut() {
+            let Some(aid) = console.association_id else {
+                continue;
+            };
+            if console.state != SimulatedConsoleState::Connected || console.silent {
+                continue;
+            }
+            let aid_bit: DsWifiClientMask = 1 << aid;
+            if client_target_mask & aid_bit == 0 {
+                continue;
+            }
+            let slot = (client_target_mask & (aid_bit - 1)).num_clients() as u16;
+            console.polls_answered += 1;
+            replies.push((slot, console.mac_address, console.reply_payload.clone()));
+        }
+        replies.sort_by_key(|(slot, _, _)| *slot);
+
+        for (slot, mac_address, payload) in replies {
+            Timer::at(tx_time + Duration::from_micros(slot as u64 * us_per_client_reply as u64)).await;
+            self.send_mp_reply(mac_address, &payload);
+        }
+    }
+
+    async fn handle_host_frame(&self, frame: MemoryTxFrame) {
+        let Ok(generic_frame) = GenericFrame::new(frame.data.as_slice(), false) else {
+            return;
+        };
+        if let FrameType::Data(DataFrameSubtype::DataCFPoll) = generic_frame.frame_control_field().frame_type() {
+            let Ok(Ok(data_frame)) = generic_frame.parse_to_typed::<DataFrame>() else {
+                return;
+            };
+            let Some(DataFrameReadPayload::Single(payload)) = data_frame.payload else {
+                return;
+            };
+            let Ok((mp_frame, _)) = HostToClientDataFrame::<&[u8]>::try_from_ctx(payload, ()) else {
+                return;
+            };
+            self.mp_polls.borrow_mut().push(SimulatedMpPoll {
+                us_per_client_reply: mp_frame.us_per_client_reply,
+                duration: frame.tx_parameters.duration,
+                client_target_mask: mp_frame.client_target_mask,
+            });
+            self.handle_mp_poll(frame.timestamp, mp_frame.us_per_client_reply, mp_frame.client_target_mask).await;
+            return;
+        }
+        if let FrameType::Data(DataFrameSubtype::DataCFAck) = generic_frame.frame_control_field().frame_type() {
+            let Ok(Ok(data_frame)) = generic_frame.parse_to_typed::<DataFrame>() else {
+                return;
+            };
+            // a fixed halfword, then the clients whose reply the host got
+            if let Some(DataFrameReadPayload::Single(&[_, _, low, high, ..])) = data_frame.payload {
+                self.mp_acks.borrow_mut().push(u16::from_le_bytes([low, high]));
+            }
+            return;
+        }
+
+        let _ = match_frames! {
+            frame.data.as_slice(),
+            _beacon = BeaconFrame => {
+                self.handle_beacon();
+            }
+            auth = AuthenticationFrame => {
+                self.handle_auth(auth.header.receiver_address, auth.body.status_code);
+            }
+            assoc = AssociationResponseFrame => {
+                self.handle_assoc_resp(
+                    assoc.header.receiver_address,
+                    assoc.body.status_code,
+                    assoc.body.association_id.map(|aid| aid.aid())
+                );
+            }
+            deauth = DeauthenticationFrame => {
+                self.handle_deauth(deauth.header.receiver_address);
+            }
+        };
+    }
+
+    pub async fn run(&self) -> ! {
+        loop {
+            let frame = self.radio.take_tx().await;
+            self.handle_host_frame(frame).await;
+        }
+    }
+}
+
+fn noop_raw_waker() -> RawWaker {
+    fn clone(_: *const ()) -> RawWaker {
+        noop_raw_waker()
+    }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    RawWaker::new(ptr::null(), &VTABLE)
+}
+
+// Polls `future` and advances the mock clock by `step` after every poll, until it completes or
+// `duration` of simulated time has passed.
+pub fn run_simulation<F: Future>(future: F, duration: Duration, step: Duration) -> Option<F::Output> {
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    let driver = MockDriver::get();
+    let end = Instant::now() + duration;
+
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return Some(output);
+        }
+        if Instant::now() >= end {
+            return None;
+        }
+        driver.advance(step);
+    }
+}
@@ -0,0 +1,59 @@
+use embassy_time::{Duration, Instant};
+use crate::{DsWiFiClientState, DsWiFiClientTransition, CLIENT_HISTORY_LEN};
+
+// Link statistics the runner keeps for every client, applications get copies of them through
+// DsWiFiControl::list_clients.
+
+// Upper bounds of the ack latency histogram buckets, the last bucket counts everything slower.
+pub const ACK_LATENCY_BUCKETS_MICROS: [u64; 5] = [500, 1000, 2000, 4000, 8000];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DsWiFiClientStats {
+    pub rssi_last: Option<i8>,
+    pub rssi_min: Option<i8>,
+    rssi_sum: i64,
+    rssi_samples: u32,
+    pub ack_latency_histogram: [u32; ACK_LATENCY_BUCKETS_MICROS.len() + 1],
+    pub missed_polls: u32,
+    pub retries: u32,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+impl DsWiFiClientStats {
+    pub fn rssi_avg(&self) -> Option<i8> {
+        if self.rssi_samples == 0 {
+            return None;
+        }
+        Some((self.rssi_sum / self.rssi_samples as i64) as i8)
+    }
+
+    pub(crate) fn record_rssi(&mut self, rssi: i8) {
+        self.rssi_last = Some(rssi);
+        self.rssi_min = Some(self.rssi_min.map_or(rssi, |min| min.min(rssi)));
+        self.rssi_sum += rssi as i64;
+        self.rssi_samples = self.rssi_samples.saturating_add(1);
+    }
+
+    pub(crate) fn record_ack_latency(&mut self, latency: Duration) {
+        let micros = latency.as_micros();
+        let bucket = ACK_LATENCY_BUCKETS_MICROS
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(ACK_LATENCY_BUCKETS_MICROS.len());
+        self.ack_latency_histogram[bucket] = self.ack_latency_histogram[bucket].saturating_add(1);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DsWiFiClientSnapshot {
+    pub mac_address: [u8; 6],
+    pub association_id: u16,
+    pub state: DsWiFiClientState,
+    pub last_heard: Instant,
+    pub stats: DsWiFiClientStats,
+    // the last state changes, oldest first
+    pub history: [Option<DsWiFiClientTransition>; CLIENT_HISTORY_LEN],
+}
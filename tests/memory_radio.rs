@@ -0,0 +1,68 @@
+use core::marker::PhantomData;
+use embassy_futures::block_on;
+use embassy_futures::select::{select, Either};
+use foa_dswifi::airtime::acked_frame_duration;
+use foa_dswifi::memory_radio::MemoryRadio;
+use foa_dswifi::radio::WiFiRate;
+use foa_dswifi::{new_ds_wifi_interface, DsWiFiInitInfo, DsWiFiSharedResources};
+use ieee80211::common::{IEEE80211AuthenticationAlgorithmNumber, IEEE80211StatusCode, SequenceControl};
+use ieee80211::mac_parser::MACAddress;
+use ieee80211::mgmt_frame::body::AuthenticationBody;
+use ieee80211::mgmt_frame::{AuthenticationFrame, ManagementFrameHeader};
+use ieee80211::scroll::Pwrite;
+use ieee80211::{element_chain, match_frames};
+
+const HOST_MAC_ADDRESS: [u8; 6] = [0x00, 0x09, 0xbf, 0x11, 0x22, 0x33];
+const CONSOLE_MAC_ADDRESS: [u8; 6] = [0x00, 0x09, 0xbf, 0x44, 0x55, 0x66];
+
+#[test]
+fn auth_request_is_answered() {
+    let radio = MemoryRadio::new(HOST_MAC_ADDRESS);
+    let mut shared_resources = DsWiFiSharedResources::default();
+    let (_control, mut runner) = new_ds_wifi_interface(&radio, &mut shared_resources, DsWiFiInitInfo::default())
+        .expect("default init info is valid");
+
+    let auth = AuthenticationFrame {
+        header: ManagementFrameHeader {
+            receiver_address: MACAddress::from(HOST_MAC_ADDRESS),
+            transmitter_address: MACAddress::from(CONSOLE_MAC_ADDRESS),
+            bssid: MACAddress::from(HOST_MAC_ADDRESS),
+            sequence_control: SequenceControl::new(),
+            ..Default::default()
+        },
+        body: AuthenticationBody {
+            authentication_algorithm_number: IEEE80211AuthenticationAlgorithmNumber::OpenSystem,
+            authentication_transaction_sequence_number: 1,
+            status_code: IEEE80211StatusCode::Success,
+            elements: element_chain!(),
+            _phantom: PhantomData,
+        },
+    };
+    let mut buffer = [0u8; 256];
+    let written = buffer.pwrite_with(auth, 0, false).unwrap();
+    assert!(radio.inject_rx(&buffer[..written], -40));
+
+    // beacons are off and nobody is connected, so the auth response is the first thing sent
+    let response = match block_on(select(runner.run(), radio.take_tx())) {
+        Either::First(_) => unreachable!(),
+        Either::Second(frame) => frame,
+    };
+    assert!(response.wait_for_ack);
+    assert_eq!(response.tx_parameters.duration, acked_frame_duration(WiFiRate::PhyRate2MS));
+
+    let mut answer = None;
+    let _ = match_frames! {
+        response.data.as_slice(),
+        auth = AuthenticationFrame => {
+            answer = Some((
+                auth.header.receiver_address,
+                auth.body.authentication_transaction_sequence_number,
+                auth.body.status_code,
+            ));
+        }
+    };
+    let (receiver_address, transaction_sequence_number, status_code) = answer.expect("expected an authentication frame");
+    assert_eq!(receiver_address, MACAddress::from(CONSOLE_MAC_ADDRESS));
+    assert_eq!(transaction_sequence_number, 2);
+    assert_eq!(status_code, IEEE80211StatusCode::Success);
+}
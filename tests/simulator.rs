@@ -0,0 +1,310 @@
+use core::future::Future;
+use core::pin::Pin;
+use std::sync::Mutex;
+use embassy_futures::join::join;
+use embassy_futures::select::{select, select4, Either, Either4};
+use embassy_time::{Duration, Instant, MockDriver, Timer};
+use foa_dswifi::airtime::{air_time_micros, FCS_SIZE, SIFS_MICROS};
+use foa_dswifi::error::DsWiFiControlError;
+use foa_dswifi::memory_radio::MemoryRadio;
+use foa_dswifi::radio::WiFiRate;
+use foa_dswifi::simulator::{run_simulation, DsSimulator, SimulatedConsole};
+use foa_dswifi::{new_ds_wifi_interface, DsWiFiBeaconContents, DsWiFiClientEvent, DsWiFiClientState, DsWiFiControl, DsWiFiControlEvent, DsWiFiInitInfo, DsWiFiSharedResources};
+
+// Runs the runner against simulated consoles on the mock time driver. The driver is global, so
+// the scenarios take turns.
+
+const HOST_MAC_ADDRESS: [u8; 6] = [0x00, 0x09, 0xbf, 0x11, 0x22, 0x33];
+const CONSOLE_A: [u8; 6] = [0x00, 0x09, 0xbf, 0x00, 0x00, 0x0a];
+const CONSOLE_B: [u8; 6] = [0x00, 0x09, 0xbf, 0x00, 0x00, 0x0b];
+
+const SIMULATION_STEP: Duration = Duration::from_micros(100);
+const SIMULATION_LIMIT: Duration = Duration::from_secs(5);
+
+static MOCK_DRIVER_LOCK: Mutex<()> = Mutex::new(());
+
+type Scenario<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+// Stands in for the application, always hands the runner an empty frame.
+async fn empty_frame_application(control: &DsWiFiControl<'_, MemoryRadio>) {
+    loop {
+        control.data_tx_signal.wait().await;
+        control.data_tx_signal_2.signal(DsWiFiControlEvent::FrameGenerated);
+    }
+}
+
+// Sets up a host with two simulated consoles and runs `scenario` until it returns.
+fn run_scenario(scenario: impl for<'a> FnOnce(&'a DsWiFiControl<'a, MemoryRadio>, &'a DsSimulator<'a>) -> Scenario<'a>) {
+    let _lock = MOCK_DRIVER_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    MockDriver::get().reset();
+
+    let radio = MemoryRadio::new(HOST_MAC_ADDRESS);
+    let mut shared_resources = DsWiFiSharedResources::default();
+    let (control, mut runner) = new_ds_wifi_interface(&radio, &mut shared_resources, DsWiFiInitInfo::default())
+        .expect("default init info is valid");
+    let simulator = DsSimulator::new(&radio, HOST_MAC_ADDRESS);
+    simulator.add_console(SimulatedConsole::new(CONSOLE_A));
+    simulator.add_console(SimulatedConsole::new(CONSOLE_B));
+
+    let scenario = scenario(&control, &simulator);
+    let finished = run_simulation(async {
+        match select4(runner.run(), simulator.run(), empty_frame_application(&control), scenario).await {
+            Either4::Fourth(()) => {}
+            _ => unreachable!(),
+        }
+    }, SIMULATION_LIMIT, SIMULATION_STEP);
+    assert!(finished.is_some(), "scenario didn't finish within {} ms of simulated time", SIMULATION_LIMIT.as_millis());
+}
+
+async fn join_both(control: &DsWiFiControl<'_, MemoryRadio>, simulator: &DsSimulator<'_>) {
+    control.set_beacons_enabled(true).await.expect("beacons can be enabled");
+    simulator.join(0);
+    simulator.join(1);
+
+    let mut connected = Vec::new();
+    while connected.len() < 2 {
+        if let DsWiFiClientEvent::Connected(mac_address) = control.event_rx.receive().await {
+            connected.push(mac_address);
+        }
+    }
+    assert!(connected.contains(&CONSOLE_A));
+    assert!(connected.contains(&CONSOLE_B));
+}
+
+async fn client_state(control: &DsWiFiControl<'_, MemoryRadio>, mac_address: [u8; 6]) -> Option<DsWiFiClientState> {
+    let clients = control.list_clients().await.expect("clients can be listed");
+    clients.iter().find(|client| client.mac_address == mac_address).map(|client| client.state)
+}
+
+#[test]
+fn two_consoles_connect() {
+    run_scenario(|control, simulator| Box::pin(async move {
+        join_both(control, simulator).await;
+
+        assert_eq!(client_state(control, CONSOLE_A).await, Some(DsWiFiClientState::Connected));
+        assert_eq!(client_state(control, CONSOLE_B).await, Some(DsWiFiClientState::Connected));
+
+        let clients = control.list_clients().await.expect("clients can be listed");
+        for client in clients {
+            let transitions: Vec<_> = client.history.iter().flatten().map(|transition| (transition.from, transition.to)).collect();
+            assert_eq!(transitions, [
+                (DsWiFiClientState::Authenticated, DsWiFiClientState::Associated),
+                (DsWiFiClientState::Associated, DsWiFiClientState::Connected),
+            ]);
+        }
+    }));
+}
+
+#[test]
+fn silent_console_misses_frames_and_errors() {
+    run_scenario(|control, simulator| Box::pin(async move {
+        join_both(control, simulator).await;
+        simulator.set_silent(1, true);
+
+        loop {
+            match control.event_rx.receive().await {
+                DsWiFiClientEvent::FrameMissed(mac_address, _) => {
+                    assert_eq!(mac_address, CONSOLE_B, "only the silent console should miss frames");
+                    break;
+                }
+                DsWiFiClientEvent::Disconnected(mac_address) => panic!("{:?} disconnected", mac_address),
+                DsWiFiClientEvent::Connected(_) => {}
+            }
+        }
+
+        while client_state(control, CONSOLE_B).await != Some(DsWiFiClientState::Errored) {
+            Timer::after_millis(10).await;
+        }
+        assert_eq!(client_state(control, CONSOLE_A).await, Some(DsWiFiClientState::Connected));
+    }));
+}
+
+#[test]
+fn deauthenticated_console_disconnects() {
+    run_scenario(|control, simulator| Box::pin(async move {
+        join_both(control, simulator).await;
+        simulator.deauth(0);
+
+        loop {
+            if let DsWiFiClientEvent::Disconnected(mac_address) = control.event_rx.receive().await {
+                assert_eq!(mac_address, CONSOLE_A);
+                break;
+            }
+        }
+
+        assert_eq!(client_state(control, CONSOLE_A).await, None);
+        assert_eq!(client_state(control, CONSOLE_B).await, Some(DsWiFiClientState::Connected));
+    }));
+}
+
+#[test]
+fn reauthenticating_console_keeps_its_aid() {
+    run_scenario(|control, simulator| Box::pin(async move {
+        join_both(control, simulator).await;
+        let aid = simulator.with_console(0, |console| console.association_id);
+        simulator.join(0);
+
+        // the stale session is dropped first, polls missed while rejoining may be reported in between
+        let mut events = Vec::new();
+        while events.last() != Some(&(true, CONSOLE_A)) {
+            match control.event_rx.receive().await {
+                DsWiFiClientEvent::Disconnected(mac_address) => events.push((false, mac_address)),
+                DsWiFiClientEvent::Connected(mac_address) => events.push((true, mac_address)),
+                DsWiFiClientEvent::FrameMissed(..) => {}
+            }
+        }
+        assert_eq!(events, [(false, CONSOLE_A), (true, CONSOLE_A)]);
+
+        let clients = control.list_clients().await.expect("clients can be listed");
+        let client = clients.iter().find(|client| client.mac_address == CONSOLE_A).expect("console A is listed");
+        assert_eq!(client.state, DsWiFiClientState::Connected);
+        assert_eq!(Some(client.association_id), aid);
+        assert_eq!(client_state(control, CONSOLE_B).await, Some(DsWiFiClientState::Connected));
+    }));
+}
+
+#[test]
+fn full_event_queue_does_not_block_control() {
+    run_scenario(|control, simulator| Box::pin(async move {
+        join_both(control, simulator).await;
+
+        // every poll console B misses is queued until the queue is full
+        simulator.set_silent(1, true);
+        while client_state(control, CONSOLE_B).await != Some(DsWiFiClientState::Errored) {
+            Timer::after_millis(10).await;
+        }
+
+        // the runner has to wait for room to report this one
+        simulator.deauth(0);
+        while client_state(control, CONSOLE_A).await.is_some() {
+            Timer::after_millis(10).await;
+        }
+
+        // console B times out meanwhile, its Disconnected may come first
+        loop {
+            if let DsWiFiClientEvent::Disconnected(CONSOLE_A) = control.event_rx.receive().await {
+                break;
+            }
+        }
+    }));
+}
+
+#[test]
+fn abandoned_scan_does_not_answer_later_requests() {
+    run_scenario(|control, _simulator| Box::pin(async move {
+        let dwell_time = Duration::from_millis(50);
+        let scan_given_up = async {
+            let scan = select(control.scan_for_hosts(dwell_time), Timer::after_millis(10)).await;
+            assert!(matches!(scan, Either::Second(())), "the scan can't be done yet");
+        };
+        let stats_requested = async {
+            Timer::after_millis(1).await;
+            control.get_stats().await.expect("stats are answered while the scan runs")
+        };
+        join(scan_given_up, stats_requested).await;
+
+        // keep asking until well past the end of the abandoned scan
+        let scan_end = Instant::now() + dwell_time * 4;
+        while Instant::now() < scan_end {
+            control.get_stats().await.expect("stats are answered");
+            Timer::after_micros(500).await;
+        }
+        assert!(control.scan_for_hosts(dwell_time).await.expect("a new scan can be started").is_empty());
+    }));
+}
+
+#[test]
+fn errored_console_recovers_when_it_answers_again() {
+    run_scenario(|control, simulator| Box::pin(async move {
+        join_both(control, simulator).await;
+        simulator.set_silent(1, true);
+        while client_state(control, CONSOLE_B).await != Some(DsWiFiClientState::Errored) {
+            Timer::after_millis(10).await;
+        }
+
+        // errored consoles are still polled now and then, well within the client timeout
+        simulator.set_silent(1, false);
+        loop {
+            match control.event_rx.receive().await {
+                DsWiFiClientEvent::Connected(mac_address) => {
+                    assert_eq!(mac_address, CONSOLE_B);
+                    break;
+                }
+                DsWiFiClientEvent::Disconnected(mac_address) => panic!("{:?} disconnected", mac_address),
+                DsWiFiClientEvent::FrameMissed(..) => {}
+            }
+        }
+        assert_eq!(client_state(control, CONSOLE_B).await, Some(DsWiFiClientState::Connected));
+        assert_eq!(client_state(control, CONSOLE_A).await, Some(DsWiFiClientState::Connected));
+    }));
+}
+
+#[test]
+fn channel_switch_disconnects_clients() {
+    run_scenario(|control, simulator| Box::pin(async move {
+        join_both(control, simulator).await;
+        control.set_channel(1).await.expect("the channel can be switched");
+
+        let mut disconnected = Vec::new();
+        while disconnected.len() < 2 {
+            if let DsWiFiClientEvent::Disconnected(mac_address) = control.event_rx.receive().await {
+                disconnected.push(mac_address);
+            }
+        }
+        assert!(disconnected.contains(&CONSOLE_A));
+        assert!(disconnected.contains(&CONSOLE_B));
+        assert!(control.list_clients().await.expect("clients can be listed").is_empty());
+        assert_eq!(control.get_stats().await.expect("stats are answered").channel, 1);
+
+        assert_eq!(control.set_channel(15).await, Err(DsWiFiControlError::InvalidParameter("channel")));
+        assert_eq!(control.get_stats().await.expect("stats are answered").channel, 1);
+    }));
+}
+
+// Checks the polls and acks heard after the beacon announced `reply_data_size`, with `clients`
+// consoles connected and answering.
+async fn check_mp_timing(control: &DsWiFiControl<'_, MemoryRadio>, simulator: &DsSimulator<'_>, reply_data_size: u16, clients: u32) {
+    control
+        .set_beacon_contents(DsWiFiBeaconContents { reply_data_size, ..DsWiFiBeaconContents::new([0x00, 0x00, 0x00, 0x00]) })
+        .await
+        .expect("the reply size fits");
+    // an exchange under way may still use the previous size
+    Timer::after_millis(100).await;
+    simulator.take_mp_polls();
+    simulator.take_mp_acks();
+    Timer::after_millis(200).await;
+
+    // header, reply header and footer and FCS around the payload, then SIFS before the next reply
+    let slot = SIFS_MICROS + air_time_micros(WiFiRate::PhyRate2MS, 24 + 2 + 2 + FCS_SIZE + reply_data_size as usize);
+    // the replies, SIFS and the 4 byte ack
+    let duration = slot * clients + SIFS_MICROS + air_time_micros(WiFiRate::PhyRate2MS, 24 + 4 + FCS_SIZE);
+    let polls = simulator.take_mp_polls();
+    assert!(!polls.is_empty());
+    for poll in &polls {
+        assert_eq!(poll.us_per_client_reply as u32, slot, "reply size {}", reply_data_size);
+        assert_eq!(poll.duration as u32, duration, "reply size {} with {} clients", reply_data_size, clients);
+        assert_eq!(poll.client_target_mask.count_ones(), clients);
+    }
+    // everyone answers, so every ack names all of them
+    let acks = simulator.take_mp_acks();
+    assert!(!acks.is_empty());
+    assert!(acks.iter().all(|replied| *replied == polls[0].client_target_mask));
+}
+
+#[test]
+fn mp_timing_follows_reply_size_and_client_count() {
+    run_scenario(|control, simulator| Box::pin(async move {
+        control.set_beacons_enabled(true).await.expect("beacons can be enabled");
+        simulator.join(0);
+        while !matches!(control.event_rx.receive().await, DsWiFiClientEvent::Connected(CONSOLE_A)) {}
+        for reply_data_size in [0, 0x40, 0xc0] {
+            check_mp_timing(control, simulator, reply_data_size, 1).await;
+        }
+
+        simulator.join(1);
+        while !matches!(control.event_rx.receive().await, DsWiFiClientEvent::Connected(CONSOLE_B)) {}
+        for reply_data_size in [0, 0x40, 0xc0] {
+            check_mp_timing(control, simulator, reply_data_size, 2).await;
+        }
+    }));
+}